@@ -1,4 +1,6 @@
 //! Contains modules relating to interfacing between Elvi and its internals.
+/// Contains modules relating to arithmetic expansion.
+pub mod arithmetic;
 /// Contains modules relating to handling commands.
 pub mod commands;
 /// Contains modules relating to builtins.
@@ -31,6 +33,8 @@ pub mod builtins {
     /// This builtin is not in the POSIX spec and is an addition to Elvi. It should function mostly
     /// like `declare -p` from Bash, but not always.
     pub mod dbg;
+    /// What the `export` and `readonly` builtins have in common.
+    pub mod declare;
     /// The `echo` builtin.
     ///
     /// Conforming to
@@ -41,11 +45,33 @@ pub mod builtins {
     /// Conforming to
     /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#exit>
     pub mod exit;
+    /// The `export` builtin.
+    ///
+    /// Conforming to
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#export>
+    pub mod export;
+    /// The `break`, `continue` and `return` builtins.
+    ///
+    /// Conforming to
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#break>,
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#continue> and
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#return>
+    pub mod flow;
     /// The `hash` builtin.
     ///
     /// Conforming to
     /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#hash>
     pub mod hash;
+    /// The `readonly` builtin.
+    ///
+    /// Conforming to
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#readonly>
+    pub mod readonly;
+    /// The `set` builtin.
+    ///
+    /// Conforming to
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#set>
+    pub mod set;
     /// The `shift` builtin.
     ///
     /// Conforming to
@@ -61,13 +87,31 @@ pub mod builtins {
     /// Conforming to
     /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#unset>
     pub mod unset;
+    /// The `wait` builtin.
+    ///
+    /// Conforming to
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/wait.html> but implemented as a
+    /// builtin.
+    pub mod wait;
 }
 /// Contains modules relating to the global state.
 pub mod env;
 /// Contains modules relating to Elvi errors.
 pub mod errors;
+/// Contains modules relating to field splitting.
+pub mod fields;
+/// Contains modules relating to pathname expansion.
+pub mod pathname;
+/// Contains modules relating to matching shell patterns.
+pub mod pattern;
+/// Contains modules relating to running pipelines and background jobs.
+pub mod pipeline;
+/// Contains modules relating to redirecting file descriptors.
+pub mod redirection;
 /// Contains modules relating to handling error codes.
 pub mod status;
+/// Contains modules relating to command substitution.
+pub mod substitution;
 /// Contains modules relating to executing Elvi code.
 pub mod tree;
 /// Contains modules relating to handling variables.
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+
+use super::errors::ArithmeticError;
+use super::variables::Variables;
+
+/// Every operator, longest first so that `<<=` isn't read as `<` and `<=`.
+const OPERATORS: [&str; 35] = [
+    "<<=", ">>=", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "*=", "/=", "%=", "+=", "-=",
+    "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~", "?", ":", "=",
+    "(", ")",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single piece of an arithmetic expression.
+enum Token {
+    /// `42`, `052` & `0x2a`
+    Number(i64),
+    /// A variable name.
+    Name(String),
+    /// Anything in [`OPERATORS`].
+    Operator(&'static str),
+}
+
+#[derive(Debug, Clone)]
+/// A parsed arithmetic expression.
+enum Expr {
+    Number(i64),
+    Variable(String),
+    /// `-x`, `+x`, `~x` & `!x`
+    Unary(&'static str, Box<Expr>),
+    /// Any binary operator, including `&&` and `||`.
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    /// `condition ? then : else`
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `x = 1`, `x += 1`, etc.
+    Assign(String, &'static str, Box<Expr>),
+}
+
+/// Evaluate an arithmetic expression, such as the inside of `$((...))`.
+///
+/// According to
+/// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_06_04>,
+/// everything is a signed 64 bit integer using the operators (and precedence) of C, and bare
+/// names are variables:
+///
+/// ```bash
+/// echo $(( (count += 2) > 3 ? 0x10 : 010 ))
+/// ```
+///
+/// # Notes
+/// Variables that get assigned are put into `assigned` instead of being set, and are read from
+/// there before `variables`, so that the caller can set them when it is done expanding.
+///
+/// # Errors
+/// Will return [`ArithmeticError`] if the expression is not valid, a variable is not a number, or
+/// on division by zero.
+pub fn evaluate(
+    expression: &str,
+    variables: &Variables,
+    assigned: &mut HashMap<String, String>,
+) -> Result<i64, ArithmeticError> {
+    let tokens = tokenize(expression)?;
+    // `$(())` is just zero.
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = ExprParser {
+        expression,
+        tokens,
+        pos: 0,
+    };
+    let expr = parser.assignment()?;
+    if let Some(token) = parser.tokens.get(parser.pos) {
+        return Err(parser.error(format!("unexpected {}", describe(token))));
+    }
+    Evaluator {
+        expression,
+        variables,
+        assigned,
+    }
+    .eval(&expr)
+}
+
+/// Parse an integer constant, which can be decimal, octal (`0` prefix) or hexadecimal (`0x`
+/// prefix), with an optional sign.
+///
+/// # Returns
+/// [`None`] if it isn't a valid number.
+#[must_use]
+pub fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        u64::from_str_radix(hex, 16).ok()?
+    } else if digits.len() > 1 && digits.starts_with('0') {
+        u64::from_str_radix(&digits[1..], 8).ok()?
+    } else if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    // Just like C, anything too big wraps around.
+    #[allow(clippy::cast_possible_wrap)]
+    let value = value as i64;
+    Some(if negative {
+        value.wrapping_neg()
+    } else {
+        value
+    })
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, ArithmeticError> {
+    let mut tokens = vec![];
+    let mut rest = expression.trim_start();
+    while let Some(charp) = rest.chars().next() {
+        if charp.is_ascii_alphanumeric() || charp == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            if charp.is_ascii_digit() {
+                tokens.push(Token::Number(parse_number(word).ok_or_else(|| {
+                    ArithmeticError::IllegalNumber {
+                        number: word.to_string(),
+                    }
+                })?));
+            } else {
+                tokens.push(Token::Name(word.to_string()));
+            }
+            rest = &rest[end..];
+        } else if let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else {
+            return Err(ArithmeticError::Syntax {
+                expression: expression.to_string(),
+                reason: format!("unexpected `{charp}`"),
+            });
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(number) => format!("`{number}`"),
+        Token::Name(name) => format!("`{name}`"),
+        Token::Operator(op) => format!("`{op}`"),
+    }
+}
+
+/// How tightly a binary operator binds, higher being tighter.
+fn precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | "<=" | ">" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        _ => return None,
+    })
+}
+
+/// A recursive descent parser for arithmetic expressions.
+struct ExprParser<'a> {
+    expression: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser<'_> {
+    fn error(&self, reason: String) -> ArithmeticError {
+        ArithmeticError::Syntax {
+            expression: self.expression.to_string(),
+            reason,
+        }
+    }
+
+    fn peek_operator(&self) -> Option<&'static str> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Operator(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, wanted: &str) -> Result<(), ArithmeticError> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Operator(op)) if *op == wanted => {
+                self.pos += 1;
+                Ok(())
+            }
+            Some(token) => {
+                Err(self.error(format!("expected `{wanted}`, found {}", describe(token))))
+            }
+            None => Err(self.error(format!("expected `{wanted}`"))),
+        }
+    }
+
+    /// `name = expr`, which is right associative and has the lowest precedence.
+    fn assignment(&mut self) -> Result<Expr, ArithmeticError> {
+        if let (Some(Token::Name(name)), Some(Token::Operator(op))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            let (name, op) = (name.clone(), *op);
+            if op.ends_with('=') && !matches!(op, "==" | "!=" | "<=" | ">=") {
+                self.pos += 2;
+                return Ok(Expr::Assign(name, op, Box::new(self.assignment()?)));
+            }
+        }
+        self.conditional()
+    }
+
+    /// `condition ? then : else`
+    fn conditional(&mut self) -> Result<Expr, ArithmeticError> {
+        let condition = self.binary(1)?;
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let then = self.assignment()?;
+        self.expect(":")?;
+        let otherwise = self.conditional()?;
+        Ok(Expr::Conditional(
+            Box::new(condition),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    /// Binary operators binding at least as tightly as `minimum`, which are left associative.
+    fn binary(&mut self, minimum: u8) -> Result<Expr, ArithmeticError> {
+        let mut left = self.unary()?;
+        while let Some(op) = self.peek_operator() {
+            let Some(prec) = precedence(op).filter(|prec| *prec >= minimum) else {
+                break;
+            };
+            self.pos += 1;
+            let right = self.binary(prec + 1)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ArithmeticError> {
+        match self.peek_operator() {
+            Some(op @ ("+" | "-" | "~" | "!")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ArithmeticError> {
+        let Some(token) = self.tokens.get(self.pos).cloned() else {
+            return Err(self.error("expression ends too early".to_string()));
+        };
+        self.pos += 1;
+        match token {
+            Token::Number(number) => Ok(Expr::Number(number)),
+            Token::Name(name) => Ok(Expr::Variable(name)),
+            Token::Operator("(") => {
+                let inner = self.assignment()?;
+                self.expect(")")?;
+                Ok(inner)
+            }
+            other => Err(self.error(format!("unexpected {}", describe(&other)))),
+        }
+    }
+}
+
+/// Evaluates a parsed expression.
+struct Evaluator<'a> {
+    expression: &'a str,
+    variables: &'a Variables,
+    assigned: &'a mut HashMap<String, String>,
+}
+
+impl Evaluator<'_> {
+    fn eval(&mut self, expr: &Expr) -> Result<i64, ArithmeticError> {
+        Ok(match expr {
+            Expr::Number(number) => *number,
+            Expr::Variable(name) => self.variable(name)?,
+            Expr::Unary(op, inner) => {
+                let value = self.eval(inner)?;
+                match *op {
+                    "-" => value.wrapping_neg(),
+                    "~" => !value,
+                    "!" => i64::from(value == 0),
+                    _ => value,
+                }
+            }
+            // These two don't evaluate their right side unless they need to.
+            Expr::Binary("&&", left, right) => {
+                i64::from(self.eval(left)? != 0 && self.eval(right)? != 0)
+            }
+            Expr::Binary("||", left, right) => {
+                i64::from(self.eval(left)? != 0 || self.eval(right)? != 0)
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                self.apply(op, left, right)?
+            }
+            Expr::Conditional(condition, then, otherwise) => {
+                if self.eval(condition)? == 0 {
+                    self.eval(otherwise)?
+                } else {
+                    self.eval(then)?
+                }
+            }
+            Expr::Assign(name, op, value) => {
+                let mut value = self.eval(value)?;
+                if *op != "=" {
+                    let current = self.variable(name)?;
+                    value = self.apply(op.trim_end_matches('='), current, value)?;
+                }
+                self.assigned.insert(name.clone(), value.to_string());
+                value
+            }
+        })
+    }
+
+    /// Read a variable as a number, where unset and empty variables are zero.
+    fn variable(&self, name: &str) -> Result<i64, ArithmeticError> {
+        let contents = match (self.assigned.get(name), self.variables.get_variable(name)) {
+            (Some(value), _) => value.clone(),
+            (None, Some(var)) => var.contents.to_string(),
+            (None, None) => return Ok(0),
+        };
+        let contents = contents.trim();
+        if contents.is_empty() {
+            return Ok(0);
+        }
+        parse_number(contents).ok_or_else(|| ArithmeticError::IllegalNumber {
+            number: contents.to_string(),
+        })
+    }
+
+    fn apply(&self, op: &str, left: i64, right: i64) -> Result<i64, ArithmeticError> {
+        // Shifting by more than 63 bits isn't defined, so only the bits that make sense are used.
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let shift = right as u32;
+        Ok(match op {
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => {
+                return Err(ArithmeticError::DivisionByZero {
+                    expression: self.expression.to_string(),
+                })
+            }
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "<<" => left.wrapping_shl(shift),
+            ">>" => left.wrapping_shr(shift),
+            "<" => i64::from(left < right),
+            "<=" => i64::from(left <= right),
+            ">" => i64::from(left > right),
+            ">=" => i64::from(left >= right),
+            "==" => i64::from(left == right),
+            "!=" => i64::from(left != right),
+            "&" => left & right,
+            "^" => left ^ right,
+            "|" => left | right,
+            _ => unreachable!("The parser only makes binary expressions with binary operators"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(expression: &str) -> Result<i64, ArithmeticError> {
+        evaluate(expression, &Variables::default(), &mut HashMap::new())
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(7, calc("1 + 2 * 3").unwrap());
+        assert_eq!(9, calc("(1 + 2) * 3").unwrap());
+        assert_eq!(2, calc("8 - 4 - 2").unwrap());
+        assert_eq!(1, calc("1 < 2 == 1").unwrap());
+        assert_eq!(3, calc("-~2").unwrap());
+        assert_eq!(7, calc("1 | 2 & 3 ^ 4 << 1 >> 1").unwrap());
+        assert_eq!(0, calc("").unwrap());
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(42, calc("42").unwrap());
+        assert_eq!(42, calc("052").unwrap());
+        assert_eq!(42, calc("0x2A").unwrap());
+        assert!(matches!(
+            calc("09"),
+            Err(ArithmeticError::IllegalNumber { .. })
+        ));
+    }
+
+    #[test]
+    fn logic_short_circuits() {
+        assert_eq!(0, calc("0 && 1 / 0").unwrap());
+        assert_eq!(1, calc("1 || 1 / 0").unwrap());
+        assert_eq!(5, calc("0 ? 1 / 0 : 2 + 3").unwrap());
+        assert_eq!(3, calc("1 ? 0 ? 2 : 3 : 4").unwrap());
+        assert!(matches!(
+            calc("5 % (2 - 2)"),
+            Err(ArithmeticError::DivisionByZero { .. })
+        ));
+    }
+
+    #[test]
+    fn assignments_are_remembered() {
+        let mut assigned = HashMap::new();
+        let vars = Variables::default();
+        assert_eq!(5, evaluate("x = y = 5", &vars, &mut assigned).unwrap());
+        assert_eq!(20, evaluate("x *= 2 + 2", &vars, &mut assigned).unwrap());
+        assert_eq!(Some("20"), assigned.get("x").map(String::as_str));
+        assert_eq!(Some("5"), assigned.get("y").map(String::as_str));
+        assert_eq!(
+            1,
+            evaluate("x == 20 && (y <<= 1)", &vars, &mut assigned).unwrap()
+        );
+        assert_eq!(Some("10"), assigned.get("y").map(String::as_str));
+    }
+}
@@ -1,18 +1,15 @@
-use std::path::PathBuf;
-use std::{env, fs};
+use std::env;
+use std::path::{Component, Path, PathBuf};
 
 use getopts::Options;
 
+use crate::internal::env::{Env, Std};
 use crate::internal::errors::{CommandError, ElviError};
 use crate::internal::status::ReturnCode;
 use crate::internal::variables::{ElviType, Variable, Variables};
 
 /// The internal code that runs when the `cd` builtin is run.
-///
-/// # Todo
-/// Fix this spaghetti code.
-#[allow(clippy::too_many_lines)]
-pub fn main(args: Option<&[ElviType]>, variables: &mut Variables) -> ReturnCode {
+pub fn main(args: Option<&[ElviType]>, variables: &mut Variables, env: &mut Env) -> ReturnCode {
     let mut opts = Options::new();
     let mut evaled_variables = vec![];
     opts.optflag("h", "help", "print help menu");
@@ -22,7 +19,6 @@ pub fn main(args: Option<&[ElviType]>, variables: &mut Variables) -> ReturnCode
             evaled_variables.push(
                 part.tilde_expansion(variables)
                     .eval_variables(variables)
-                    .eval_escapes()
                     .to_string(),
             );
         }
@@ -36,99 +32,131 @@ pub fn main(args: Option<&[ElviType]>, variables: &mut Variables) -> ReturnCode
         }
     };
     if matches.opt_present("h") {
-        print_usage("cd", &opts);
+        print_usage("cd", &opts, env);
         return ReturnCode::SUCCESS.into();
     }
 
-    if matches.free.is_empty() {
-        match variables.set_variable("OLDPWD", variables.get_variable("PWD").unwrap().clone()) {
-            Ok(()) => {}
-            Err(oops) => eprintln!("{oops}"),
-        }
-        match variables.set_variable("PWD", variables.get_variable("HOME").unwrap().clone()) {
-            Ok(()) => {}
-            Err(oops) => eprintln!("{oops}"),
-        }
-        assert!(
-            env::set_current_dir(variables.get_variable("HOME").unwrap().contents.to_string())
-                .is_ok()
-        );
-        return ReturnCode::SUCCESS.into();
-    }
-    // Atp we know we got something, so let's check if it's `-` or a path.
-    match matches.free[0].as_str() {
-        "-" => {
-            let swap = variables.get_variable("PWD").unwrap().clone();
-            match variables.set_variable("PWD", variables.get_variable("OLDPWD").unwrap().clone()) {
-                Ok(()) => {}
-                Err(oops) => eprintln!("{oops}"),
+    let dir = match matches.free.first().map(String::as_str) {
+        // `$HOME` and `$OLDPWD` are left unset when they couldn't be found at startup.
+        None => match variables.get_variable("HOME") {
+            Some(home) => home.contents.to_string(),
+            None => {
+                eprintln!("elvi: cd: HOME not set");
+                return ReturnCode::FAILURE.into();
             }
-            println!("{}", variables.get_variable("PWD").unwrap().contents);
-            match variables.set_variable("OLDPWD", swap) {
-                Ok(()) => {}
-                Err(oops) => eprintln!("{oops}"),
+        },
+        Some("-") => match variables.get_variable("OLDPWD") {
+            Some(old_pwd) => old_pwd.contents.to_string(),
+            None => {
+                eprintln!("elvi: cd: OLDPWD not set");
+                return ReturnCode::FAILURE.into();
             }
-            assert!(env::set_current_dir(
-                variables.get_variable("PWD").unwrap().contents.to_string()
-            )
-            .is_ok());
-            ReturnCode::SUCCESS.into()
+        },
+        Some(patho) => ElviType::String(patho.to_string())
+            .tilde_expansion(variables)
+            .to_string(),
+    };
+
+    // The directory can refuse to be entered, like when it doesn't exist or without search
+    // permission, so `cd dir || exit` has to fail instead of panicking.
+    if let Err(err) = enter(&dir, variables) {
+        eprintln!("{err}");
+        return err.ret();
+    }
+    if matches.free.first().is_some_and(|arg| arg == "-") {
+        if let Some(pwd) = variables.get_variable("PWD") {
+            let _ = env.print(&Std::Out, format!("{}\n", pwd.contents));
         }
-        patho => {
-            let to_cd = PathBuf::from(
-                ElviType::String(patho.to_string())
-                    .tilde_expansion(variables)
-                    .to_string(),
-            );
-            if !to_cd.exists() {
-                let err = CommandError::CannotCd {
-                    name: "cd".to_string(),
-                    path: to_cd.to_str().unwrap().to_string(),
-                };
-                eprintln!("{err}");
-                return err.ret();
-            }
-            if fs::read_dir(to_cd.to_str().unwrap()).is_err() {
-                let err = CommandError::CannotCd {
-                    name: "cd".to_string(),
-                    path: to_cd.to_str().unwrap().to_string(),
-                };
-                eprintln!("{err}");
-                return err.ret();
-            }
-            // Ok so the path exists, time to roll.
-            match variables.set_variable(
-                "OLDPWD",
-                match variables.get_variable("PWD") {
-                    Some(yay) => yay.to_owned(),
-                    None => Variable {
-                        contents: ElviType::String(
-                            env::current_dir().unwrap().to_str().unwrap().into(),
-                        ),
-                        ..Default::default()
-                    },
-                },
-            ) {
-                Ok(()) => {}
-                Err(oops) => eprintln!("{oops}"),
-            }
-            match variables.set_variable(
-                "PWD",
-                Variable {
-                    contents: ElviType::String(to_cd.to_str().unwrap().to_string()),
-                    ..Default::default()
-                },
-            ) {
-                Ok(()) => {}
-                Err(oops) => eprintln!("{oops}"),
+    }
+    ReturnCode::SUCCESS.into()
+}
+
+/// Enter `dir`, pointing `$OLDPWD` at the directory that was left and `$PWD` at the new one.
+///
+/// Like `cd -L`, a relative `dir` is joined onto `$PWD` and its `.` and `..` are dropped without
+/// following symbolic links, so `$PWD` stays absolute and keeps the path that was taken.
+fn enter(dir: &str, variables: &mut Variables) -> Result<(), CommandError> {
+    // Without a usable `$PWD`, the directory we're leaving is used, unless it was deleted or isn't
+    // UTF-8.
+    let old_pwd = variables
+        .get_variable("PWD")
+        .map(|pwd| pwd.contents.to_string())
+        .filter(|pwd| Path::new(pwd).is_absolute())
+        .or_else(|| {
+            env::current_dir()
+                .ok()
+                .and_then(|dir| dir.into_os_string().into_string().ok())
+        });
+    let target = match &old_pwd {
+        Some(old_pwd) => normalize(&Path::new(old_pwd).join(dir)),
+        None => PathBuf::from(dir),
+    };
+    if env::set_current_dir(&target).is_err() {
+        return Err(CommandError::CannotCd {
+            name: "cd".to_string(),
+            path: dir.to_string(),
+        });
+    }
+    let new_pwd = if target.is_absolute() {
+        Some(target.to_string_lossy().into_owned())
+    } else {
+        env::current_dir()
+            .ok()
+            .and_then(|dir| dir.into_os_string().into_string().ok())
+    };
+
+    if let Some(old_pwd) = old_pwd {
+        set_path(variables, "OLDPWD", old_pwd);
+    }
+    if let Some(new_pwd) = new_pwd {
+        set_path(variables, "PWD", new_pwd);
+    }
+    Ok(())
+}
+
+/// Drop the `.` and `..` components of `path` without looking at the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normal.pop();
             }
-            assert!(env::set_current_dir(to_cd).is_ok());
-            ReturnCode::SUCCESS.into()
+            other => normal.push(other),
         }
     }
+    normal
 }
 
-fn print_usage(program: &str, opts: &Options) {
+/// Set `name` to `path`, keeping whatever else it already had, like being exported.
+fn set_path(variables: &mut Variables, name: &str, path: String) {
+    let contents = ElviType::String(path);
+    let var = match variables.get_variable(name) {
+        Some(template) => Variable {
+            contents,
+            ..template.clone()
+        },
+        None => contents.into(),
+    };
+    if let Err(oops) = variables.set_variable(name, var) {
+        eprintln!("{oops}");
+    }
+}
+
+fn print_usage(program: &str, opts: &Options, env: &mut Env) {
     let brief = format!("Usage: {program} PATH");
-    print!("{}", opts.usage(&brief));
+    let _ = env.print(&Std::Out, &opts.usage(&brief));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_drops_dots_without_following_links() {
+        assert_eq!(PathBuf::from("/a/c"), normalize(Path::new("/a/./b/../c")));
+        assert_eq!(PathBuf::from("/"), normalize(Path::new("/a/../..")));
+        assert_eq!(PathBuf::from("/a/b"), normalize(Path::new("/a/b/.")));
+    }
 }
@@ -1,11 +1,12 @@
 use getopts::Options;
 
-use crate::internal::errors::{ElviError, VariableError};
+use crate::internal::env::{Env, Std};
+use crate::internal::errors::{describe_io_error, ElviError, VariableError};
 use crate::internal::status::ReturnCode;
 use crate::internal::variables::{ElviMutable, ElviType, Variables};
 
 /// The internal code that runs when the `dbg` builtin is run.
-pub fn main(args: Option<&[ElviType]>, variables: &mut Variables) -> ReturnCode {
+pub fn main(args: Option<&[ElviType]>, variables: &mut Variables, env: &mut Env) -> ReturnCode {
     let mut opts = Options::new();
     let mut evaled_variables = vec![];
     opts.optflag("h", "help", "print help menu");
@@ -15,7 +16,6 @@ pub fn main(args: Option<&[ElviType]>, variables: &mut Variables) -> ReturnCode
             evaled_variables.push(
                 part.tilde_expansion(variables)
                     .eval_variables(variables)
-                    .eval_escapes()
                     .to_string(),
             );
         }
@@ -29,12 +29,12 @@ pub fn main(args: Option<&[ElviType]>, variables: &mut Variables) -> ReturnCode
         }
     };
     if matches.opt_present("h") {
-        print_usage("dbg", &opts);
+        print_usage("dbg", &opts, env);
         return ReturnCode::SUCCESS.into();
     }
 
     if matches.free.is_empty() {
-        print_usage("dbg", &opts);
+        print_usage("dbg", &opts, env);
         return ReturnCode::FAILURE.into();
     }
 
@@ -47,16 +47,22 @@ pub fn main(args: Option<&[ElviType]>, variables: &mut Variables) -> ReturnCode
         return err.ret();
     };
 
-    match var.modification_status {
-        ElviMutable::Normal => println!("{}={:?}", matches.free[0], var.contents),
+    let output = match var.modification_status {
+        ElviMutable::Normal => format!("{}={:?}\n", matches.free[0], var.contents),
         ElviMutable::Readonly | ElviMutable::ReadonlyUnsettable => {
-            println!("readonly {}={}", matches.free[0], var.contents);
+            format!("readonly {}={}\n", matches.free[0], var.contents)
+        }
+    };
+    match env.print(&Std::Out, &output) {
+        Ok(()) => ReturnCode::SUCCESS.into(),
+        Err(oops) => {
+            eprintln!("elvi: dbg: {}", describe_io_error(&oops));
+            ReturnCode::FAILURE.into()
         }
     }
-    ReturnCode::SUCCESS.into()
 }
 
-fn print_usage(program: &str, opts: &Options) {
+fn print_usage(program: &str, opts: &Options, env: &mut Env) {
     let brief = format!("Usage: {program} VARNAME");
-    print!("{}", opts.usage(&brief));
+    let _ = env.print(&Std::Out, &opts.usage(&brief));
 }
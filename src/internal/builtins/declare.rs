@@ -0,0 +1,99 @@
+use getopts::Options;
+
+use crate::internal::env::{Env, Std};
+use crate::internal::errors::{describe_io_error, ElviError, VariableError};
+use crate::internal::status::ReturnCode;
+use crate::internal::variables::{is_name, quote, ElviType, Variables};
+
+/// Variables to print, along with their values if they're set.
+pub type Listing<'a> = Vec<(&'a String, Option<String>)>;
+
+/// Run `program`, which is either `export` or `readonly`.
+///
+/// Every `name[=value]` operand with a valid name is given to `declare`, which does whatever
+/// `program` does to it. Without any operands, or with `-p`, the variables `listed` are printed
+/// instead, along with their values if they're set.
+pub fn main(
+    program: &'static str,
+    args: Option<&[ElviType]>,
+    variables: &mut Variables,
+    env: &mut Env,
+    listed: fn(&Variables) -> Listing<'_>,
+    mut declare: impl FnMut(&mut Variables, &str, Option<&str>) -> Result<(), VariableError>,
+) -> ReturnCode {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print help menu");
+    opts.optflag(
+        "p",
+        "",
+        &format!("print every variable {program} applies to"),
+    );
+
+    let args: Vec<String> = args
+        .unwrap_or_default()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    let matches = match opts.parse(args) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{f}");
+            return ReturnCode::MISUSE.into();
+        }
+    };
+    if matches.opt_present("h") {
+        print_usage(program, &opts);
+        return ReturnCode::SUCCESS.into();
+    }
+    if matches.free.is_empty() {
+        return print_declarations(program, listed(variables), env);
+    }
+
+    let mut return_code: ReturnCode = ReturnCode::SUCCESS.into();
+    for operand in &matches.free {
+        let (name, value) = match operand.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (operand.as_str(), None),
+        };
+        if !is_name(name) {
+            let err = VariableError::BadName {
+                name: name.to_string(),
+                caller: program,
+            };
+            eprintln!("{err}");
+            return_code = err.ret();
+            continue;
+        }
+        if let Err(oops) = declare(variables, name, value) {
+            eprintln!("{oops}");
+            return_code = oops.ret();
+        }
+    }
+    return_code
+}
+
+/// Print `program name='value'` for every variable, sorted by name, or just `program name` for the
+/// ones that aren't set.
+fn print_declarations(program: &str, mut lines: Listing, env: &mut Env) -> ReturnCode {
+    lines.sort();
+    let output: String = lines
+        .into_iter()
+        .map(|(name, value)| match value {
+            Some(value) => format!("{program} {name}={}\n", quote(&value)),
+            None => format!("{program} {name}\n"),
+        })
+        .collect();
+
+    match env.print(&Std::Out, &output) {
+        Ok(()) => ReturnCode::SUCCESS.into(),
+        Err(oops) => {
+            eprintln!("elvi: {program}: {}", describe_io_error(&oops));
+            ReturnCode::FAILURE.into()
+        }
+    }
+}
+
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {program} [-p] [name[=value] ...]");
+    print!("{}", opts.usage(&brief));
+}
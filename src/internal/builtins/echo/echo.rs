@@ -1,10 +1,11 @@
+use crate::internal::env::{Env, Std};
+use crate::internal::errors::describe_io_error;
 use crate::internal::status::ReturnCode;
-use crate::internal::variables::{ElviType, Variables};
+use crate::internal::variables::{unescape, ElviType, Escapes, Variables};
 use getopts::Options;
 
 /// The internal code that runs when the `echo` builtin is run.
-#[must_use]
-pub fn main(text: Option<&[ElviType]>, variables: &Variables) -> ReturnCode {
+pub fn main(text: Option<&[ElviType]>, variables: &Variables, env: &mut Env) -> ReturnCode {
     let mut opts = Options::new();
     let mut evaled_variables = vec![];
     opts.optflag("n", "", "do not append a newline");
@@ -14,7 +15,6 @@ pub fn main(text: Option<&[ElviType]>, variables: &Variables) -> ReturnCode {
             evaled_variables.push(
                 part.tilde_expansion(variables)
                     .eval_variables(variables)
-                    .eval_escapes()
                     .to_string(),
             );
         }
@@ -28,23 +28,16 @@ pub fn main(text: Option<&[ElviType]>, variables: &Variables) -> ReturnCode {
         }
     };
 
-    let mut to_print = vec![];
-    if matches.free.is_empty() {
-        if matches.opt_present("n") {
-            print!("");
-        } else {
-            println!();
-        }
-        ReturnCode::SUCCESS.into()
-    } else {
-        for part in &matches.free {
-            to_print.push(part.to_owned().replace("\\033", "\x1b"));
-        }
-        if matches.opt_present("n") {
-            print!("{}", to_print.join(" "));
-        } else {
-            println!("{}", to_print.join(" "));
+    let (mut output, stopped) = unescape(&matches.free.join(" "), Escapes::Echo);
+    if !matches.opt_present("n") && !stopped {
+        output.push(b'\n');
+    }
+
+    match env.print(&Std::Out, &output) {
+        Ok(()) => ReturnCode::SUCCESS.into(),
+        Err(oops) => {
+            eprintln!("elvi: echo: {}", describe_io_error(&oops));
+            ReturnCode::FAILURE.into()
         }
-        ReturnCode::SUCCESS.into()
     }
 }
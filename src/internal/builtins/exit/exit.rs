@@ -22,7 +22,6 @@ pub fn main(args: Option<&[ElviType]>, variables: &Variables) -> ReturnCode {
             evaled_variables.push(
                 part.tilde_expansion(variables)
                     .eval_variables(variables)
-                    .eval_escapes()
                     .to_string(),
             );
         }
@@ -54,8 +53,8 @@ pub fn main(args: Option<&[ElviType]>, variables: &Variables) -> ReturnCode {
                 err.ret()
             }
         }
-        // If we have a bare exit, return with 0.
-        None => ReturnCode::SUCCESS.into(),
+        // A bare exit keeps the return code of the last command.
+        None => variables.get_ret().convert_err_type(),
     }
 }
 
@@ -0,0 +1,37 @@
+use crate::internal::builtins::declare::{self, Listing};
+use crate::internal::env::Env;
+use crate::internal::status::ReturnCode;
+use crate::internal::variables::{ElviType, Variables};
+
+/// The internal code that runs when the `export` builtin is run.
+///
+/// Every name given is exported to the commands that are run from now on, and `name=value` sets
+/// it at the same time. Without any names, or with `-p`, the exported variables are printed in a
+/// way that can be read back in.
+pub fn main(args: Option<&[ElviType]>, variables: &mut Variables, env: &mut Env) -> ReturnCode {
+    declare::main(
+        "export",
+        args,
+        variables,
+        env,
+        exported,
+        |variables, name, value| {
+            if let Some(value) = value {
+                variables.set_variable(name, ElviType::String(value.into()))?;
+            }
+            variables.export(name);
+            Ok(())
+        },
+    )
+}
+
+/// Every exported variable, along with the ones that aren't set yet.
+fn exported(variables: &Variables) -> Listing<'_> {
+    variables
+        .vars
+        .iter()
+        .filter(|(_, var)| var.exported)
+        .map(|(name, var)| (name, Some(var.contents.to_string())))
+        .chain(variables.exports.iter().map(|name| (name, None)))
+        .collect()
+}
@@ -0,0 +1,64 @@
+use crate::internal::env::{ControlFlow, Env};
+use crate::internal::errors::{ElviError, VariableError};
+use crate::internal::status::ReturnCode;
+use crate::internal::variables::{ElviType, Variables};
+
+/// The internal code that runs when the `break` or `continue` builtin is run.
+///
+/// Leaves `n` loops, or goes to the next iteration of the `n`th loop out, where `n` is `1` if it
+/// isn't given and the outermost loop if there aren't that many. Outside of a loop, nothing
+/// happens at all.
+pub fn loops(continuing: bool, args: Option<&[ElviType]>, global_env: &mut Env) -> ReturnCode {
+    let caller = if continuing { "continue" } else { "break" };
+    let levels = match args.unwrap_or_default().first() {
+        Some(levels) => match levels.to_string().parse::<u32>() {
+            Ok(levels) if levels > 0 => levels,
+            _ => {
+                let err = VariableError::IllegalNumber {
+                    name: levels.to_string(),
+                    caller,
+                };
+                eprintln!("{err}");
+                return err.ret();
+            }
+        },
+        None => 1,
+    };
+    if global_env.loops > 0 {
+        let levels = levels.min(global_env.loops);
+        global_env.flow = Some(if continuing {
+            ControlFlow::Continue(levels)
+        } else {
+            ControlFlow::Break(levels)
+        });
+    }
+    ReturnCode::SUCCESS.into()
+}
+
+/// The internal code that runs when the `return` builtin is run.
+///
+/// Leaves the function with the return code given, or the one of the last command if there
+/// isn't one. Outside of a function, it's an error instead.
+pub fn ret(args: Option<&[ElviType]>, variables: &Variables, global_env: &mut Env) -> ReturnCode {
+    if !global_env.in_function() {
+        let err = VariableError::NotInFunction { name: "return" };
+        eprintln!("{err}");
+        return err.ret();
+    }
+    let ret = match args.unwrap_or_default().first() {
+        Some(code) => {
+            let Ok(code) = code.to_string().parse::<ReturnCode>() else {
+                let err = VariableError::IllegalNumber {
+                    name: code.to_string(),
+                    caller: "return",
+                };
+                eprintln!("{err}");
+                return err.ret();
+            };
+            code
+        }
+        None => variables.get_ret().convert_err_type(),
+    };
+    global_env.flow = Some(ControlFlow::Return);
+    ret
+}
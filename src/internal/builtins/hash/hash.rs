@@ -1,6 +1,7 @@
 use getopts::Options;
 
 use crate::internal::commands::Commands;
+use crate::internal::env::{Env, Std};
 use crate::internal::status::ReturnCode;
 use crate::internal::variables::{ElviType, Variables};
 
@@ -9,6 +10,7 @@ pub fn main(
     args: Option<&[ElviType]>,
     commands: &mut Commands,
     variables: &Variables,
+    env: &mut Env,
 ) -> ReturnCode {
     let mut opts = Options::new();
     let mut evaled_variables = vec![];
@@ -19,7 +21,6 @@ pub fn main(
             evaled_variables.push(
                 part.tilde_expansion(variables)
                     .eval_variables(variables)
-                    .eval_escapes()
                     .to_string(),
             );
         }
@@ -37,17 +38,22 @@ pub fn main(
         *commands = Commands::generate(variables);
     } else if matches.free.is_empty() {
         for (cmd, patho) in &commands.cmds {
-            println!("{}={}", cmd, patho.display());
+            if env
+                .print(&Std::Out, &format!("{}={}\n", cmd, patho.display()))
+                .is_err()
+            {
+                return ReturnCode::FAILURE.into();
+            }
         }
     } else {
-        print_usage("hash", &opts);
+        print_usage("hash", &opts, env);
         return ReturnCode::MISUSE.into();
     }
 
     ReturnCode::SUCCESS.into()
 }
 
-fn print_usage(program: &str, opts: &Options) {
+fn print_usage(program: &str, opts: &Options, env: &mut Env) {
     let brief = format!("Usage: {program} [-r] [name ...]");
-    print!("{}", opts.usage(&brief));
+    let _ = env.print(&Std::Out, &opts.usage(&brief));
 }
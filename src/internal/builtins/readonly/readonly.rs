@@ -0,0 +1,104 @@
+use crate::internal::builtins::declare::{self, Listing};
+use crate::internal::env::Env;
+use crate::internal::status::ReturnCode;
+use crate::internal::variables::{ElviMutable, ElviType, Variable, Variables};
+
+/// The internal code that runs when the `readonly` builtin is run.
+///
+/// Every name given can't be changed or unset from now on, and `name=value` sets it first.
+/// Without any names, or with `-p`, the readonly variables are printed in a way that can be read
+/// back in.
+///
+/// The line and column of the builtin are what errors point to later on.
+pub fn main(
+    args: Option<&[ElviType]>,
+    position: (usize, usize),
+    variables: &mut Variables,
+    env: &mut Env,
+) -> ReturnCode {
+    declare::main(
+        "readonly",
+        args,
+        variables,
+        env,
+        frozen,
+        |variables, name, value| {
+            match value {
+                Some(value) => {
+                    let var = Variable {
+                        contents: ElviType::String(value.into()),
+                        modification_status: ElviMutable::Readonly,
+                        line: position,
+                        ..Default::default()
+                    };
+                    variables.set_variable(name, var)?;
+                }
+                None => variables.freeze(name, position),
+            }
+            Ok(())
+        },
+    )
+}
+
+/// Every readonly variable, along with the ones that aren't set.
+///
+/// Special parameters like `$?` are left out, since they can't be set at all.
+fn frozen(variables: &Variables) -> Listing<'_> {
+    variables
+        .vars
+        .iter()
+        .filter(|(_, var)| matches!(var.modification_status, ElviMutable::Readonly))
+        .map(|(name, var)| (name, Some(var.contents.to_string())))
+        .chain(variables.frozen.keys().map(|name| (name, None)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::errors::VariableError;
+
+    #[test]
+    fn freezes_every_operand_where_it_was_run() {
+        let mut variables = Variables::default();
+        let mut env = Env::default();
+        variables
+            .set_variable("foo", ElviType::String("bar".into()))
+            .unwrap();
+        let ret = main(
+            Some(&["foo", "baz=1", "unset"].map(|arg| ElviType::String(arg.into()))),
+            (4, 2),
+            &mut variables,
+            &mut env,
+        );
+        assert_eq!(ReturnCode::SUCCESS, ret.get());
+        for name in ["foo", "baz", "unset"] {
+            let Err(VariableError::Readonly { lines, .. }) =
+                variables.set_variable(name, ElviType::String("new".into()))
+            else {
+                panic!("{name} can still be set");
+            };
+            assert_eq!((4, 2), lines);
+        }
+        assert_eq!(
+            "bar",
+            variables.get_variable("foo").unwrap().contents.to_string()
+        );
+        assert!(variables.get_variable("unset").is_none());
+    }
+
+    #[test]
+    fn bad_names_are_not_frozen() {
+        let mut variables = Variables::default();
+        let mut env = Env::default();
+        let ret = main(
+            Some(&["9lives=1", "ok"].map(|arg| ElviType::String(arg.into()))),
+            (1, 1),
+            &mut variables,
+            &mut env,
+        );
+        assert_eq!(ReturnCode::MISUSE, ret.get());
+        assert!(variables.frozen.contains_key("ok"));
+        assert!(!variables.frozen.contains_key("9lives"));
+    }
+}
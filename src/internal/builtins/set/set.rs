@@ -0,0 +1,226 @@
+use std::iter;
+
+use crate::internal::env::{Env, Std};
+use crate::internal::errors::describe_io_error;
+use crate::internal::status::ReturnCode;
+use crate::internal::variables::{is_name, quote, ElviType, Variable, Variables};
+
+/// Options that can be turned on and off with `set`, their names for `-o`, and what they do.
+const OPTIONS: &[(char, &str, &str)] = &[
+    ('a', "allexport", "export every variable that is assigned"),
+    ('C', "noclobber", "don't let `>` overwrite files"),
+    ('e', "errexit", "exit as soon as a command fails"),
+    ('f', "noglob", "disable pathname expansion"),
+    ('n', "noexec", "read commands without running them"),
+    (
+        'u',
+        "nounset",
+        "fail when expanding a parameter that isn't set",
+    ),
+    ('v', "verbose", "print input as it is read"),
+    ('x', "xtrace", "print commands before they are run"),
+];
+
+/// The internal code that runs when the `set` builtin is run.
+///
+/// `-f` turns an option on, and `+f` turns it back off, as does `-o noglob` and `+o noglob`.
+/// Whatever comes after the options, or after `--`, replaces the positional parameters.
+///
+/// Without any arguments, every variable is printed in a way that can be read back in, and `-o`
+/// or `+o` on their own print the options instead.
+pub fn main(args: Option<&[ElviType]>, variables: &mut Variables, env: &mut Env) -> ReturnCode {
+    let args: Vec<String> = args
+        .unwrap_or_default()
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    if args.is_empty() {
+        return print_variables(variables, env);
+    }
+
+    let mut args = args.into_iter();
+    let mut operands = None;
+    while let Some(arg) = args.next() {
+        // `--` replaces the positional parameters even if nothing follows it, while `-` only does
+        // if something does.
+        if arg == "--" {
+            operands = Some(args.by_ref().collect());
+            break;
+        }
+        if arg == "-" {
+            variables.set_flag('x', false);
+            variables.set_flag('v', false);
+            let rest: Vec<String> = args.by_ref().collect();
+            operands = (!rest.is_empty()).then_some(rest);
+            break;
+        }
+        let on = match arg.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => {
+                operands = Some(iter::once(arg).chain(args.by_ref()).collect());
+                break;
+            }
+        };
+        for flag in arg.chars().skip(1) {
+            if flag == 'o' {
+                let Some(name) = args.next() else {
+                    return print_options(on, variables, env);
+                };
+                match OPTIONS.iter().find(|(_, long, _)| *long == name) {
+                    Some((option, ..)) => variables.set_flag(*option, on),
+                    None => {
+                        eprintln!("elvi: set: illegal option {}o {name}", &arg[..1]);
+                        print_usage("set");
+                        return ReturnCode::MISUSE.into();
+                    }
+                }
+                continue;
+            }
+            if !OPTIONS.iter().any(|(option, ..)| *option == flag) {
+                eprintln!("elvi: set: illegal option {}{flag}", &arg[..1]);
+                print_usage("set");
+                return ReturnCode::MISUSE.into();
+            }
+            variables.set_flag(flag, on);
+        }
+    }
+
+    if let Some(operands) = operands {
+        // `$0` stays where it is.
+        let params: Vec<Variable> = variables
+            .params
+            .first()
+            .cloned()
+            .into_iter()
+            .chain(operands.into_iter().map(Variable::from))
+            .collect();
+        variables.new_parameters(&params);
+    }
+    ReturnCode::SUCCESS.into()
+}
+
+/// Print `name='value'` for every variable, sorted by name.
+///
+/// Special parameters like `$?` are left out, since they can't be set at all.
+fn print_variables(variables: &Variables, env: &mut Env) -> ReturnCode {
+    let mut lines: Vec<(&String, String)> = variables
+        .vars
+        .iter()
+        .filter(|(name, _)| is_name(name))
+        .map(|(name, var)| (name, var.contents.to_string()))
+        .collect();
+    lines.sort();
+    let output: String = lines
+        .into_iter()
+        .map(|(name, value)| format!("{name}={}\n", quote(&value)))
+        .collect();
+    print_output(&output, env)
+}
+
+/// Print a table of whether every option is on or off, like `-o` does, or the `set` commands that
+/// would turn them back on or off, like `+o` does.
+fn print_options(table: bool, variables: &Variables, env: &mut Env) -> ReturnCode {
+    let output: String = OPTIONS
+        .iter()
+        .map(|(option, name, _)| {
+            let on = variables.has_flag(*option);
+            match (table, on) {
+                (true, true) => format!("{name:<16}on\n"),
+                (true, false) => format!("{name:<16}off\n"),
+                (false, true) => format!("set -o {name}\n"),
+                (false, false) => format!("set +o {name}\n"),
+            }
+        })
+        .collect();
+    print_output(&output, env)
+}
+
+fn print_output(output: &str, env: &mut Env) -> ReturnCode {
+    match env.print(&Std::Out, output) {
+        Ok(()) => ReturnCode::SUCCESS.into(),
+        Err(oops) => {
+            eprintln!("elvi: set: {}", describe_io_error(&oops));
+            ReturnCode::FAILURE.into()
+        }
+    }
+}
+
+fn print_usage(program: &str) {
+    eprintln!("Usage: {program} [-+aCefnuvx] [-+o name] [--] [arg ...]");
+    for (option, name, description) in OPTIONS {
+        eprintln!("    -{option}, -o {name:<12}{description}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(variables: &Variables) -> Vec<String> {
+        variables
+            .params
+            .iter()
+            .map(|param| param.contents.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn options_show_up_in_dollar_dash() {
+        let mut variables = Variables::default();
+        let mut env = Env::default();
+        let ret = main(
+            Some(&["-eu", "-o", "noglob", "+e"].map(|arg| ElviType::String(arg.into()))),
+            &mut variables,
+            &mut env,
+        );
+        assert_eq!(ReturnCode::SUCCESS, ret.get());
+        assert!(variables.has_flag('u'));
+        assert!(variables.has_flag('f'));
+        assert!(!variables.has_flag('e'));
+
+        let ret = main(
+            Some(&["-o", "nope"].map(|arg| ElviType::String(arg.into()))),
+            &mut variables,
+            &mut env,
+        );
+        assert_eq!(ReturnCode::MISUSE, ret.get());
+    }
+
+    #[test]
+    fn operands_replace_positional_parameters() {
+        let mut variables = Variables::default();
+        let mut env = Env::default();
+        variables.new_parameters(&["elvi".to_string().into(), "old".to_string().into()]);
+
+        main(
+            Some(&["-f", "a", "-b"].map(|arg| ElviType::String(arg.into()))),
+            &mut variables,
+            &mut env,
+        );
+        assert_eq!(vec!["elvi", "a", "-b"], params(&variables));
+        assert!(variables.has_flag('f'));
+
+        main(
+            Some(&["-"].map(|arg| ElviType::String(arg.into()))),
+            &mut variables,
+            &mut env,
+        );
+        assert_eq!(vec!["elvi", "a", "-b"], params(&variables));
+
+        main(
+            Some(&["--", "-x"].map(|arg| ElviType::String(arg.into()))),
+            &mut variables,
+            &mut env,
+        );
+        assert_eq!(vec!["elvi", "-x"], params(&variables));
+        assert!(!variables.has_flag('x'));
+
+        main(
+            Some(&["--"].map(|arg| ElviType::String(arg.into()))),
+            &mut variables,
+            &mut env,
+        );
+        assert_eq!(vec!["elvi"], params(&variables));
+    }
+}
@@ -15,7 +15,6 @@ pub fn main(args: Option<&[ElviType]>, variables: &mut Variables) -> ReturnCode
             evaled_variables.push(
                 part.tilde_expansion(variables)
                     .eval_variables(variables)
-                    .eval_escapes()
                     .to_string(),
             );
         }
@@ -32,7 +32,6 @@ pub fn main(
             evaled_variables.push(
                 part.tilde_expansion(variables)
                     .eval_variables(variables)
-                    .eval_escapes()
                     .to_string(),
             );
         }
@@ -66,8 +65,19 @@ pub fn main(
             commands.deregister_function(&name);
         } else {
             let Some(var) = variables.get_variable(&name) else {
+                if let Some(lines) = variables.frozen.get(&name) {
+                    let err = VariableError::Readonly {
+                        name: name.clone(),
+                        lines: *lines,
+                    };
+                    eprintln!("{err}");
+                    return_code = ReturnCode::FAILURE.into();
+                    continue;
+                }
                 // <https://pubs.opengroup.org/onlinepubs/9699919799.2018edition/utilities/V3_chap02.html#unset> in description in 5th paragraph
-                return ReturnCode::SUCCESS.into();
+                // It can still have been given to `export` though.
+                variables.unset(&name);
+                continue;
             };
             match var.modification_status {
                 ElviMutable::Normal => match variables.unset(&name) {
@@ -76,7 +86,7 @@ pub fn main(
                 },
                 ElviMutable::Readonly | ElviMutable::ReadonlyUnsettable => {
                     let err = VariableError::Readonly {
-                        name: "unset".to_string(),
+                        name: name.clone(),
                         lines: var.line,
                     };
                     eprintln!("{err}");
@@ -0,0 +1,72 @@
+use getopts::Options;
+
+use crate::internal::env::Env;
+use crate::internal::errors::{ElviError, VariableError};
+use crate::internal::pipeline::wait_pid;
+use crate::internal::status::ReturnCode;
+use crate::internal::variables::{ElviType, Variables};
+
+/// The internal code that runs when the `wait` builtin is run.
+///
+/// Without any process IDs, every background job is waited for and the return code is `0`,
+/// otherwise it is the return code of the last one given, or `127` if it isn't a job of this shell.
+pub fn main(args: Option<&[ElviType]>, variables: &Variables, env: &mut Env) -> ReturnCode {
+    let mut opts = Options::new();
+    let mut evaled_variables = vec![];
+    opts.optflag("h", "help", "print help message");
+
+    if let Some(unny) = args {
+        for part in unny {
+            evaled_variables.push(
+                part.tilde_expansion(variables)
+                    .eval_variables(variables)
+                    .to_string(),
+            );
+        }
+    }
+
+    let matches = match opts.parse(evaled_variables) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{f}");
+            return ReturnCode::MISUSE.into();
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage("wait", &opts);
+        return ReturnCode::SUCCESS.into();
+    }
+
+    if matches.free.is_empty() {
+        for pid in std::mem::take(&mut env.jobs) {
+            wait_pid(pid);
+        }
+        return ReturnCode::SUCCESS.into();
+    }
+
+    let mut ret = ReturnCode::SUCCESS.into();
+    for number in matches.free {
+        let Ok(pid) = number.parse::<libc::pid_t>() else {
+            let err = VariableError::IllegalNumber {
+                name: number,
+                caller: "wait",
+            };
+            eprintln!("{err}");
+            return err.ret();
+        };
+        ret = match env.jobs.iter().position(|&job| job == pid) {
+            Some(idx) => {
+                env.jobs.remove(idx);
+                wait_pid(pid)
+            }
+            None => ReturnCode::COMMAND_NOT_FOUND.into(),
+        };
+    }
+    ret
+}
+
+fn print_usage(program: &str, opts: &Options) {
+    let brief = format!("Usage: {program} [pid...]");
+    print!("{}", opts.usage(&brief));
+}
@@ -35,23 +35,25 @@ pub struct ExternalCommand {
     pub cmd: PathBuf,
     /// Arguments (if any).
     pub args: Option<Vec<String>>,
-    /// Attributes of how a command should be run.
-    pub attributes: HowRun,
 }
 
 impl Commands {
-    /// Generate a list of commands from a path variable.
+    /// Generate a list of commands from a path variable, which is empty if `PATH` isn't set.
     ///
     /// # Panics
-    /// 1. This will panic if it cannot find the `PATH` variable, which in practice won't happen.
-    /// 2. Will panic if `PATH` is not defined as an [`ElviType::String`].
+    /// Will panic if `PATH` is not defined as an [`ElviType::String`].
     #[must_use = "Why are you generating PATH if you aren't using it"]
     pub fn generate(variables: &Variables) -> Self {
         let mut cmds: HashMap<String, PathBuf> = HashMap::new();
 
-        let path_var = &variables.get_variable("PATH").unwrap().contents;
+        let Some(path_var) = variables.get_variable("PATH") else {
+            return Self {
+                cmds,
+                functions: HashMap::new(),
+            };
+        };
 
-        let ElviType::String(path_var) = path_var else {
+        let ElviType::String(path_var) = &path_var.contents else {
             unreachable!("How is `PATH` defined as anything but a string? For your debugging information, it is {:?}", path_var)
         };
 
@@ -110,7 +112,6 @@ impl<T: Deref<Target = str>> From<&T> for ExternalCommand {
             } else {
                 Some(split_up.iter().skip(1).map(|s| (*s).to_string()).collect())
             },
-            attributes: HowRun::RealTime,
         }
     }
 }
@@ -125,7 +126,6 @@ impl From<Vec<String>> for ExternalCommand {
             } else {
                 Some(value.iter().skip(1).map(|s| (*s).to_string()).collect())
             },
-            attributes: HowRun::RealTime,
         }
     }
 }
@@ -137,31 +137,6 @@ pub struct CmdReturn {
     pub stderr: Vec<u8>,
 }
 
-#[derive(Debug, Clone)]
-/// Instructs [`ExternalCommand`] how to handle output.
-pub enum HowRun {
-    /// Run in real time. Use this when you want immediate output, such as when you are not piping
-    /// into anything.
-    ///
-    /// ```bash
-    /// ls -la
-    /// ```
-    RealTime,
-    /// Use this when you are doing command substitution. It functions like [`HowRun::Piped`] but
-    /// should print stderr out after running.
-    ///
-    /// ```bash
-    /// foo=`ls -la`
-    /// ```
-    Substitution,
-    /// When you are piping into multiple commands.
-    ///
-    /// ```bash
-    /// ls | less
-    /// ```
-    Piped,
-}
-
 impl Default for CmdReturn {
     fn default() -> Self {
         Self {
@@ -235,14 +210,16 @@ pub fn execute_external_command(
     // 1. Create command that takes our full path because we have already calculated it by PATH.
     // 2. Clear environment.
     // 3. Insert our own.
-    // 4. Set current directory based on PWD.
+    // 4. Set current directory based on PWD, or leave it as the shell's own without one.
     let bitch = Command::new("");
     let mut binding = Command::new(cmd_to_run);
     let bruh = binding
         .args(cmd.args.unwrap_or_default())
         .env_clear()
-        .current_dir(variables.get_variable("PWD").unwrap().contents.to_string())
         .envs(filtered_env);
+    if let Some(pwd) = variables.get_variable("PWD") {
+        bruh.current_dir(pwd.contents.to_string());
+    }
     // So this was the only way I could figure out how to swap mutability and satisfy the borrow
     // checker.
     Ok(mem::replace(bruh, bitch))
@@ -1,14 +1,38 @@
-use super::{
-    commands::HowRun,
-    variables::{ElviType, Variable, Variables},
-};
+use std::io::{self, Write};
+
+use super::variables::{ElviType, Variable, Variables};
+use crate::user_flags::Extension;
 
 /// Struct to handle the global environment.
 pub struct Env {
     in_function: bool,
     pub subshells_in: u32,
-    pub stdout: String,
-    pub stderr: String,
+    /// Extensions turned on from the command line, needed to parse code while running.
+    pub extensions: Vec<Extension>,
+    /// Process IDs of background jobs that haven't been waited for yet.
+    pub jobs: Vec<libc::pid_t>,
+    /// How many loops the code running right now is in, not counting the ones around the
+    /// function it's in.
+    pub loops: u32,
+    /// Set by `break`, `continue`, `return` and `exit` to stop running everything up to
+    /// whatever they leave.
+    pub flow: Option<ControlFlow>,
+    /// How many places the code running right now is in where `-e` is ignored, like the condition
+    /// of an `if`.
+    pub errexit_ignored: u32,
+}
+
+/// A way of leaving what is being run early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFlow {
+    /// Leave this many loops.
+    Break(u32),
+    /// Go to the next iteration of the loop this many loops out.
+    Continue(u32),
+    /// Leave the function.
+    Return,
+    /// Leave the subshell, or the whole script outside of one.
+    Exit,
 }
 
 #[derive(Copy, Clone)]
@@ -42,23 +66,55 @@ impl Env {
         self.subshells_in
     }
 
+    /// Handle a `break` or `continue` once an iteration of a loop is over.
+    ///
+    /// # Returns
+    /// Whether the loop has to stop, which is also the case for `return` and `exit`.
+    pub fn end_iteration(&mut self) -> bool {
+        match self.flow {
+            Some(ControlFlow::Break(levels)) => {
+                self.flow = (levels > 1).then(|| ControlFlow::Break(levels - 1));
+                true
+            }
+            Some(ControlFlow::Continue(levels)) if levels > 1 => {
+                self.flow = Some(ControlFlow::Continue(levels - 1));
+                true
+            }
+            Some(ControlFlow::Continue(_)) => {
+                self.flow = None;
+                false
+            }
+            Some(ControlFlow::Return | ControlFlow::Exit) => true,
+            None => false,
+        }
+    }
+
     /// Query whether inside a function or not.
     #[must_use]
     pub fn in_function(&self) -> bool {
         self.in_function
     }
 
-    /// Append or print text to screen
-    pub fn print(&mut self, whereto: &Std, how: &HowRun, text: &str) {
-        match how {
-            HowRun::RealTime => match whereto {
-                Std::Out => print!("{text}"),
-                Std::Err => eprint!("{text}"),
-            },
-            HowRun::Piped | HowRun::Substitution => match whereto {
-                Std::Out => self.stdout.push_str(text),
-                Std::Err => self.stderr.push_str(text),
-            },
+    /// Print text to screen
+    ///
+    /// The text can be any bytes, since not everything printed (like `echo '\0377'`) is UTF-8.
+    ///
+    /// # Notes
+    /// Text goes to whatever file descriptors 1 and 2 are right now, so it respects any
+    /// redirections.
+    ///
+    /// # Errors
+    /// Will return an error if the text cannot be written, such as when stdout was closed with
+    /// `>&-`.
+    pub fn print(&mut self, whereto: &Std, text: impl AsRef<[u8]>) -> io::Result<()> {
+        let text = text.as_ref();
+        match whereto {
+            Std::Out => {
+                let mut stdout = io::stdout().lock();
+                stdout.write_all(text)?;
+                stdout.flush()
+            }
+            Std::Err => io::stderr().write_all(text),
         }
     }
 }
@@ -68,8 +124,11 @@ impl Default for Env {
         Self {
             in_function: false,
             subshells_in: 1,
-            stdout: String::new(),
-            stderr: String::new(),
+            extensions: vec![],
+            jobs: vec![],
+            loops: 0,
+            flow: None,
+            errexit_ignored: 0,
         }
     }
 }
@@ -1,5 +1,17 @@
+use std::io;
+
 use super::status::ReturnCode;
 
+/// Turn an [`io::Error`] into a message without the `(os error N)` at the end.
+#[must_use]
+pub fn describe_io_error(oops: &io::Error) -> String {
+    let message = oops.to_string();
+    match message.split_once(" (os error") {
+        Some((start, _)) => start.to_string(),
+        None => message,
+    }
+}
+
 /// Supplies information relevant to errors.
 pub trait ElviError {
     /// Give return code for a given error type.
@@ -42,10 +54,36 @@ impl std::fmt::Display for CommandError {
 #[derive(Debug)]
 /// Errors relating to variables.
 pub enum VariableError {
-    Readonly { name: String, lines: (usize, usize) },
-    IllegalNumber { name: String, caller: &'static str },
-    NoSuchVariable { name: String, caller: &'static str },
-    NotInFunction { name: &'static str },
+    Readonly {
+        name: String,
+        lines: (usize, usize),
+    },
+    IllegalNumber {
+        name: String,
+        caller: &'static str,
+    },
+    NoSuchVariable {
+        name: String,
+        caller: &'static str,
+    },
+    NotInFunction {
+        name: &'static str,
+    },
+    NotSet {
+        name: String,
+        message: String,
+        line: usize,
+    },
+    BadSubstitution {
+        text: String,
+    },
+    CannotAssign {
+        name: String,
+    },
+    BadName {
+        name: String,
+        caller: &'static str,
+    },
 }
 
 impl std::error::Error for VariableError {}
@@ -54,9 +92,13 @@ impl ElviError for VariableError {
     fn ret(&self) -> ReturnCode {
         match self {
             Self::NoSuchVariable { .. } => ReturnCode::FAILURE.into(),
-            Self::Readonly { .. } | Self::IllegalNumber { .. } | Self::NotInFunction { .. } => {
-                ReturnCode::MISUSE.into()
-            }
+            Self::Readonly { .. }
+            | Self::IllegalNumber { .. }
+            | Self::NotInFunction { .. }
+            | Self::NotSet { .. }
+            | Self::BadSubstitution { .. }
+            | Self::CannotAssign { .. }
+            | Self::BadName { .. } => ReturnCode::MISUSE.into(),
         }
     }
 }
@@ -69,7 +111,7 @@ impl std::fmt::Display for VariableError {
             }
             Self::Readonly { name, lines } => write!(
                 f,
-                "elvi: {name}: readonly variable (set on line '{}' column '{}')",
+                "elvi: {name}: readonly variable (made readonly on line '{}' column '{}')",
                 lines.0, lines.1
             ),
             Self::IllegalNumber { name, caller } => {
@@ -78,6 +120,85 @@ impl std::fmt::Display for VariableError {
             Self::NotInFunction { name } => {
                 write!(f, "elvi: {name}: not in a function")
             }
+            Self::NotSet {
+                name,
+                message,
+                line,
+            } => write!(f, "elvi: line {line}: {name}: {message}"),
+            Self::BadSubstitution { text } => write!(f, "elvi: ${{{text}}}: bad substitution"),
+            Self::CannotAssign { name } => write!(f, "elvi: ${name}: cannot assign in this way"),
+            Self::BadName { name, caller } => {
+                write!(f, "elvi: {caller}: {name}: bad variable name")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Errors relating to redirections.
+pub enum RedirectionError {
+    CannotOpen { path: String, reason: String },
+    BadFd { fd: String },
+    HereDocument { reason: String },
+}
+
+impl std::error::Error for RedirectionError {}
+
+impl ElviError for RedirectionError {
+    fn ret(&self) -> ReturnCode {
+        match self {
+            Self::CannotOpen { .. } | Self::BadFd { .. } | Self::HereDocument { .. } => {
+                ReturnCode::FAILURE.into()
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for RedirectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::CannotOpen { path, reason } => write!(f, "elvi: cannot open {path}: {reason}"),
+            Self::BadFd { fd } => write!(f, "elvi: {fd}: Bad file descriptor"),
+            Self::HereDocument { reason } => {
+                write!(f, "elvi: cannot create here-document: {reason}")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Errors relating to arithmetic expansion.
+pub enum ArithmeticError {
+    Syntax { expression: String, reason: String },
+    IllegalNumber { number: String },
+    DivisionByZero { expression: String },
+}
+
+impl std::error::Error for ArithmeticError {}
+
+impl ElviError for ArithmeticError {
+    fn ret(&self) -> ReturnCode {
+        match self {
+            Self::Syntax { .. } | Self::IllegalNumber { .. } | Self::DivisionByZero { .. } => {
+                ReturnCode::MISUSE.into()
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ArithmeticError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Syntax { expression, reason } => {
+                write!(f, "elvi: arithmetic expression: {reason}: \"{expression}\"")
+            }
+            Self::IllegalNumber { number } => write!(f, "elvi: Illegal number: {number}"),
+            Self::DivisionByZero { expression } => {
+                write!(
+                    f,
+                    "elvi: arithmetic expression: division by zero: \"{expression}\""
+                )
+            }
         }
     }
 }
@@ -0,0 +1,297 @@
+use std::mem;
+
+use glob::Pattern;
+
+use super::pathname::expand_pathname;
+use super::variables::Variables;
+
+/// What `$IFS` is when it isn't set.
+const DEFAULT_IFS: &str = " \t\n";
+
+/// The fields a word expands to, built up a piece at a time while it is expanded.
+///
+/// The results of expansions are split on `$IFS`, according to
+/// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_06_05>.
+///
+/// # Notes
+/// Only the results of expansions are split, and everything else in the word is kept as it is:
+///
+/// ```bash
+/// IFS=:
+/// foo="a:b"
+/// echo c:d $foo # Prints `c:d a b`
+/// ```
+///
+/// Every field also keeps track of which of its characters were quoted, so that only an unquoted
+/// `*`, `?` or `[` does pathname expansion.
+#[derive(Debug, Default)]
+pub struct Fields {
+    /// What to split on, or [`None`] if nothing is split.
+    ifs: Option<String>,
+    /// Whether the word is quoted, which always makes a field, even an empty one.
+    quoted: bool,
+    /// Every field that is finished.
+    fields: Vec<String>,
+    /// The field being built.
+    current: String,
+    /// Whether there is a field being built, which can still be empty.
+    started: bool,
+    /// Whether the last field was ended by `$IFS` whitespace, which an `$IFS` character that
+    /// isn't whitespace right after is part of.
+    after_whitespace: bool,
+    /// The pattern of every finished field, or [`None`] if it doesn't need pathname expansion.
+    patterns: Vec<Option<String>>,
+    /// The field being built as a pattern, where quoted characters are escaped.
+    pattern: String,
+    /// Whether the field being built has an unquoted `*`, `?` or `[` in it.
+    globbing: bool,
+}
+
+impl Fields {
+    /// Fields that are split on `$IFS`, where an empty `$IFS` doesn't split anything.
+    #[must_use]
+    pub fn split(vars: &Variables) -> Self {
+        let ifs = match vars.get_variable("IFS") {
+            Some(ifs) => ifs.contents.to_string(),
+            None => DEFAULT_IFS.into(),
+        };
+        Self {
+            ifs: (!ifs.is_empty()).then_some(ifs),
+            ..Default::default()
+        }
+    }
+
+    /// Fields of a quoted word, which is never split, and is one field unless it has `"$@"` in it.
+    #[must_use]
+    pub fn quoted() -> Self {
+        Self {
+            quoted: true,
+            started: true,
+            ..Default::default()
+        }
+    }
+
+    /// Start or stop a quoted part of a word, like the `"$dir"` in `"$dir"/*.txt`.
+    ///
+    /// Nothing in a quoted part is split, and it makes a field even if it is empty.
+    pub fn set_quoted(&mut self, quoted: bool) {
+        if quoted {
+            self.started = true;
+            self.after_whitespace = false;
+        }
+        self.quoted = quoted;
+    }
+
+    /// Add a character that is never split, and is quoted so it only ever matches itself.
+    pub fn push_char(&mut self, charp: char) {
+        self.current.push(charp);
+        self.pattern
+            .push_str(&Pattern::escape(charp.encode_utf8(&mut [0; 4])));
+        self.started = true;
+        self.after_whitespace = false;
+    }
+
+    /// Add a character that is never split, but isn't quoted either, so it can be part of a
+    /// pattern.
+    pub fn push_unquoted(&mut self, charp: char) {
+        self.current.push(charp);
+        // `**` means something else to [`glob`], but it's the same as `*` here.
+        if !(charp == '*' && self.pattern.ends_with('*')) {
+            self.pattern.push(charp);
+        }
+        self.globbing |= matches!(charp, '*' | '?' | '[');
+        self.started = true;
+        self.after_whitespace = false;
+    }
+
+    /// Add text that is never split.
+    pub fn push_literal(&mut self, text: &str) {
+        text.chars().for_each(|charp| self.push_char(charp));
+    }
+
+    /// Add the result of an expansion, splitting it into fields.
+    pub fn push_expanded(&mut self, text: &str) {
+        if self.quoted {
+            self.push_literal(text);
+            return;
+        }
+        let Some(ifs) = self.ifs.take() else {
+            text.chars().for_each(|charp| self.push_unquoted(charp));
+            return;
+        };
+        for charp in text.chars() {
+            if !ifs.contains(charp) {
+                self.push_unquoted(charp);
+            } else if matches!(charp, ' ' | '\t' | '\n') {
+                // Any amount of whitespace is one delimiter, and it's ignored at the start.
+                if self.started {
+                    self.end_field();
+                    self.after_whitespace = true;
+                }
+            } else if self.after_whitespace {
+                self.after_whitespace = false;
+            } else {
+                // Every other delimiter ends a field, even an empty one.
+                self.end_field();
+            }
+        }
+        self.ifs = Some(ifs);
+    }
+
+    /// End the field being built and start a new one, like between the parameters of `"$@"`.
+    ///
+    /// Unquoted, a field that is empty is dropped instead.
+    pub fn break_field(&mut self) {
+        if self.quoted || self.started {
+            self.end_field();
+        }
+        self.after_whitespace = false;
+    }
+
+    /// Drop the field being built if it is still empty, because `"$@"` without any positional
+    /// parameters is no field at all.
+    pub fn discard_empty(&mut self) {
+        if self.current.is_empty() {
+            self.started = false;
+        }
+    }
+
+    /// Get every field, where a field that was never started doesn't count.
+    #[must_use]
+    pub fn finish(mut self) -> Vec<String> {
+        if self.started {
+            self.end_field();
+        }
+        self.fields
+    }
+
+    /// Get every field like [`Fields::finish`], but with the fields that are patterns replaced by
+    /// the pathnames they match, unless `set -f` turned that off.
+    ///
+    /// A pattern that doesn't match anything is left as it is.
+    #[must_use]
+    pub fn finish_pathnames(mut self, vars: &Variables) -> Vec<String> {
+        if self.started {
+            self.end_field();
+        }
+        let noglob = vars.has_flag('f');
+        self.fields
+            .into_iter()
+            .zip(self.patterns)
+            .flat_map(|(field, pattern)| {
+                pattern
+                    .filter(|_| !noglob)
+                    .and_then(|pattern| expand_pathname(&pattern, vars))
+                    .unwrap_or_else(|| vec![field])
+            })
+            .collect()
+    }
+
+    /// Get everything as one string, where separate fields are joined with spaces.
+    #[must_use]
+    pub fn into_string(mut self) -> String {
+        self.fields.push(self.current);
+        self.fields.join(" ")
+    }
+
+    fn end_field(&mut self) {
+        self.fields.push(mem::take(&mut self.current));
+        let pattern = mem::take(&mut self.pattern);
+        self.patterns
+            .push(mem::take(&mut self.globbing).then_some(pattern));
+        // Quoted, the next field is there even if nothing is ever added to it.
+        self.started = self.quoted;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::variables::{ElviType, Variable};
+
+    fn split(ifs: &str, text: &str) -> Vec<String> {
+        let mut vars = Variables::default();
+        vars.set_variable("IFS", Variable::from(ElviType::String(ifs.into())))
+            .unwrap();
+        let mut fields = Fields::split(&vars);
+        fields.push_expanded(text);
+        fields.finish()
+    }
+
+    #[test]
+    fn whitespace_is_one_delimiter() {
+        assert_eq!(
+            vec!["foo", "bar", "baz"],
+            split(" \t\n", "  foo \t bar\nbaz  ")
+        );
+        assert!(split(" \t\n", " \n ").is_empty());
+    }
+
+    #[test]
+    fn other_delimiters_make_empty_fields() {
+        assert_eq!(vec!["a", "", "b"], split(":", "a::b"));
+        assert_eq!(vec!["", "a"], split(":", ":a"));
+        assert_eq!(vec!["a"], split(":", "a:"));
+        assert_eq!(vec!["a", "b", "", "c"], split(" :", "a : b : : c "));
+    }
+
+    #[test]
+    fn empty_ifs_splits_nothing() {
+        assert_eq!(vec!["a b:c"], split("", "a b:c"));
+        assert!(split("", "").is_empty());
+    }
+
+    #[test]
+    fn literals_are_not_split() {
+        let mut fields = Fields::split(&Variables::default());
+        fields.push_literal("a b");
+        fields.push_expanded("c d");
+        fields.push_literal("e");
+        assert_eq!(vec!["a bc", "de"], fields.finish());
+    }
+
+    #[test]
+    fn quoted_parts_are_not_split() {
+        let mut fields = Fields::split(&Variables::default());
+        fields.set_quoted(true);
+        fields.push_expanded("a b");
+        fields.set_quoted(false);
+        fields.push_expanded("c d");
+        assert_eq!(vec!["a bc", "d"], fields.finish());
+
+        // An empty quoted part is still a field.
+        let mut fields = Fields::split(&Variables::default());
+        fields.set_quoted(true);
+        fields.set_quoted(false);
+        fields.push_expanded(" ");
+        assert_eq!(vec![""], fields.finish());
+    }
+
+    #[test]
+    fn only_unquoted_patterns_match_pathnames() {
+        let dir = std::env::temp_dir().join(format!("elvi-fields-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["a.tmp", "b.tmp", ".hidden.tmp", "*.tmp"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        let mut vars = Variables::default();
+        vars.set_variable(
+            "PWD",
+            Variable::from(ElviType::String(dir.to_string_lossy().into())),
+        )
+        .unwrap();
+        let expand = |quoted: bool, vars: &Variables| {
+            let mut fields = Fields::split(vars);
+            fields.set_quoted(quoted);
+            fields.push_expanded("*");
+            fields.set_quoted(false);
+            fields.push_expanded(".tmp");
+            fields.finish_pathnames(vars)
+        };
+        assert_eq!(vec!["*.tmp", "a.tmp", "b.tmp"], expand(false, &vars));
+        assert_eq!(vec!["*.tmp"], expand(true, &vars));
+        vars.set_flag('f', true);
+        assert_eq!(vec!["*.tmp"], expand(false, &vars));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+use glob::{glob_with, MatchOptions, Pattern};
+
+use super::variables::Variables;
+
+/// Expand a pattern into the pathnames it matches, according to
+/// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_13_03>.
+///
+/// A `/` or a leading `.` in a filename has to be matched literally, so `*` doesn't match
+/// hidden files, and relative patterns are matched from `$PWD`:
+///
+/// ```bash
+/// rm *.tmp
+/// ls /etc/*.conf .*rc
+/// ```
+///
+/// # Returns
+/// Every pathname that matches, sorted, or [`None`] if nothing does or the pattern isn't valid.
+#[must_use]
+pub fn expand_pathname(pattern: &str, vars: &Variables) -> Option<Vec<String>> {
+    let options = MatchOptions {
+        case_sensitive: true,
+        require_literal_separator: true,
+        require_literal_leading_dot: true,
+    };
+    // [`glob_with`] leaves out hidden files altogether when it has to match a leading dot
+    // literally, so they're listed and then checked against the whole pattern instead.
+    let listing = MatchOptions {
+        require_literal_leading_dot: false,
+        ..options
+    };
+    let prefix = match vars.get_variable("PWD") {
+        Some(pwd) if !pattern.starts_with('/') => {
+            format!("{}/", pwd.contents.to_string().trim_end_matches('/'))
+        }
+        _ => String::new(),
+    };
+    let full = format!("{}{pattern}", Pattern::escape(&prefix));
+    // The paths found never end in a slash, even when the pattern does.
+    let matcher = Pattern::new(full.trim_end_matches('/')).ok()?;
+    let mut paths: Vec<String> = glob_with(&full, listing)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|path| matcher.matches_path_with(path, options))
+        .map(|path| {
+            let path = path.to_string_lossy();
+            let mut path = path.strip_prefix(&prefix).unwrap_or(&path).to_string();
+            // Only directories can match a pattern ending in a slash, and they keep it.
+            if pattern.ends_with('/') && !path.ends_with('/') {
+                path.push('/');
+            }
+            path
+        })
+        .collect();
+    if paths.is_empty() {
+        return None;
+    }
+    paths.sort();
+    Some(paths)
+}
@@ -0,0 +1,278 @@
+use super::variables::{ElviType, Substitution, Variables};
+
+/// A shell pattern, used by `case`.
+///
+/// According to <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_13>.
+///
+/// # Notes
+/// Patterns are built up in parts, because quoted parts of a pattern have to match literally:
+///
+/// ```bash
+/// case "foo*" in
+///   "foo*") echo "Only matches foo*" ;;
+///   foo*) echo "Matches anything starting with foo" ;;
+/// esac
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pattern {
+    /// Every character of the pattern, in order.
+    tokens: Vec<PatternToken>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single character of a [`Pattern`].
+enum PatternToken {
+    /// Matches exactly this character.
+    Literal(char),
+    /// `?`
+    AnyChar,
+    /// `*`
+    AnyString,
+    /// `[...]` & `[!...]`
+    Bracket {
+        /// Whether the bracket expression matches any character *not* in it.
+        negated: bool,
+        /// What the bracket expression matches.
+        items: Vec<BracketItem>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// A single item in a bracket expression.
+enum BracketItem {
+    /// `a`
+    Char(char),
+    /// `a-z`
+    Range(char, char),
+    /// `[:alpha:]`
+    Class(String),
+}
+
+impl Pattern {
+    /// Create an empty pattern, which only matches an empty string.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Build a pattern out of the words that make it up, expanding variables in them, see
+    /// [`ElviType::eval_variables_with`] for `substitute`.
+    ///
+    /// Quoted words will only ever match literally.
+    #[must_use]
+    pub fn from_parts(
+        parts: &[ElviType],
+        variables: &Variables,
+        substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+    ) -> Self {
+        let mut pattern = Self::new();
+        for part in parts {
+            match part {
+                // A backslash quotes the character after it, which then only matches itself.
+                ElviType::BareString(text) => {
+                    for (text, quoted) in part.expand_operand_parts(text, variables, substitute) {
+                        if quoted {
+                            pattern.push_literal(&text);
+                        } else {
+                            pattern.push_pattern(&text);
+                        }
+                    }
+                }
+                ElviType::VariableSubstitution(_) => {
+                    pattern
+                        .push_literal(&part.eval_variables_with(variables, substitute).to_string());
+                }
+                literal => pattern.push_literal(&literal.to_string()),
+            }
+        }
+        pattern
+    }
+
+    /// Add text to the pattern where `*`, `?`, `[` and `\` are special.
+    pub fn push_pattern(&mut self, text: &str) {
+        let chars: Vec<char> = text.chars().collect();
+        let mut idx = 0;
+        while idx < chars.len() {
+            match chars[idx] {
+                '*' => self.tokens.push(PatternToken::AnyString),
+                '?' => self.tokens.push(PatternToken::AnyChar),
+                '\\' if idx + 1 < chars.len() => {
+                    idx += 1;
+                    self.tokens.push(PatternToken::Literal(chars[idx]));
+                }
+                '[' => {
+                    // A `[` without a closing `]` is just a `[`.
+                    if let Some((bracket, end)) = parse_bracket(&chars, idx + 1) {
+                        self.tokens.push(bracket);
+                        idx = end;
+                    } else {
+                        self.tokens.push(PatternToken::Literal('['));
+                    }
+                }
+                charp => self.tokens.push(PatternToken::Literal(charp)),
+            }
+            idx += 1;
+        }
+    }
+
+    /// Add text to the pattern that only matches itself.
+    pub fn push_literal(&mut self, text: &str) {
+        self.tokens.extend(text.chars().map(PatternToken::Literal));
+    }
+
+    /// Check if the whole of `text` matches the pattern.
+    #[must_use]
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        let (mut tok, mut idx) = (0, 0);
+        // Where the last `*` was, and where in the text it started matching from, so we can go
+        // back and have it match one more character when we get stuck.
+        let mut star: Option<(usize, usize)> = None;
+        while idx < chars.len() {
+            match self.tokens.get(tok) {
+                Some(PatternToken::AnyString) => {
+                    star = Some((tok, idx));
+                    tok += 1;
+                    continue;
+                }
+                Some(token) if token.matches_char(chars[idx]) => {
+                    tok += 1;
+                    idx += 1;
+                    continue;
+                }
+                _ => {}
+            }
+            match star {
+                Some((star_tok, star_idx)) => {
+                    tok = star_tok + 1;
+                    idx = star_idx + 1;
+                    star = Some((star_tok, star_idx + 1));
+                }
+                None => return false,
+            }
+        }
+        self.tokens[tok..]
+            .iter()
+            .all(|token| *token == PatternToken::AnyString)
+    }
+}
+
+impl PatternToken {
+    /// Check if a single character matches. [`PatternToken::AnyString`] is handled by
+    /// [`Pattern::matches`].
+    fn matches_char(&self, charp: char) -> bool {
+        match self {
+            Self::Literal(literal) => *literal == charp,
+            Self::AnyChar => true,
+            Self::AnyString => false,
+            Self::Bracket { negated, items } => {
+                items.iter().any(|item| item.matches_char(charp)) != *negated
+            }
+        }
+    }
+}
+
+impl BracketItem {
+    fn matches_char(&self, charp: char) -> bool {
+        match self {
+            Self::Char(item) => *item == charp,
+            Self::Range(start, end) => (*start..=*end).contains(&charp),
+            Self::Class(class) => match class.as_str() {
+                "alnum" => charp.is_alphanumeric(),
+                "alpha" => charp.is_alphabetic(),
+                "blank" => charp == ' ' || charp == '\t',
+                "cntrl" => charp.is_control(),
+                "digit" => charp.is_ascii_digit(),
+                "graph" => charp.is_ascii_graphic(),
+                "lower" => charp.is_lowercase(),
+                "print" => charp.is_ascii_graphic() || charp == ' ',
+                "punct" => charp.is_ascii_punctuation(),
+                "space" => charp.is_whitespace(),
+                "upper" => charp.is_uppercase(),
+                "xdigit" => charp.is_ascii_hexdigit(),
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Parse a bracket expression, starting right after the `[`.
+///
+/// # Returns
+/// The bracket expression and the index of its closing `]`, or [`None`] if it never closes.
+fn parse_bracket(chars: &[char], start: usize) -> Option<(PatternToken, usize)> {
+    let mut idx = start;
+    let negated = matches!(chars.get(idx), Some('!' | '^'));
+    if negated {
+        idx += 1;
+    }
+    let mut items = vec![];
+    // A `]` right at the start is part of the expression, not the end of it.
+    let first = idx;
+    loop {
+        let charp = *chars.get(idx)?;
+        match charp {
+            ']' if idx != first => return Some((PatternToken::Bracket { negated, items }, idx)),
+            '[' if chars.get(idx + 1) == Some(&':') => {
+                let rest: String = chars[idx + 2..].iter().collect();
+                let name_len = rest.find(":]")?;
+                items.push(BracketItem::Class(rest[..name_len].to_string()));
+                idx += 2 + rest[..name_len].chars().count() + 2;
+                continue;
+            }
+            '\\' if idx + 1 < chars.len() => {
+                idx += 1;
+                items.push(BracketItem::Char(chars[idx]));
+            }
+            _ if chars.get(idx + 1) == Some(&'-')
+                && chars.get(idx + 2).is_some_and(|c| *c != ']') =>
+            {
+                items.push(BracketItem::Range(charp, chars[idx + 2]));
+                idx += 2;
+            }
+            _ => items.push(BracketItem::Char(charp)),
+        }
+        idx += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(text: &str) -> Pattern {
+        let mut pattern = Pattern::new();
+        pattern.push_pattern(text);
+        pattern
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(pattern("*").matches(""));
+        assert!(pattern("foo*").matches("foobar"));
+        assert!(pattern("*.txt").matches("a.b.txt"));
+        assert!(!pattern("*.txt").matches("a.txt.bak"));
+        assert!(pattern("?a*b").matches("xab"));
+        assert!(!pattern("?").matches(""));
+    }
+
+    #[test]
+    fn brackets() {
+        assert!(pattern("[abc]").matches("b"));
+        assert!(pattern("[!abc]").matches("d"));
+        assert!(!pattern("[!abc]").matches("a"));
+        assert!(pattern("[a-z][0-9]").matches("q7"));
+        assert!(pattern("[]]").matches("]"));
+        assert!(pattern("[[:upper:]]*").matches("Foo"));
+        assert!(pattern("[").matches("["));
+    }
+
+    #[test]
+    fn literal_parts_stay_literal() {
+        let mut pattern = pattern("foo");
+        pattern.push_literal("*");
+        assert!(pattern.matches("foo*"));
+        assert!(!pattern.matches("foobar"));
+        assert!(self::pattern(r"\*").matches("*"));
+    }
+}
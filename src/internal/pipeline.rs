@@ -0,0 +1,177 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use crate::parse::grammar::eval;
+
+use super::{
+    commands::Commands,
+    env::Env,
+    status::ReturnCode,
+    tree::Actions,
+    variables::{ElviType, Variables},
+};
+
+/// Create a pipe, returning the `(read, write)` ends.
+///
+/// Both ends are marked close-on-exec, so only the file descriptors that get duplicated onto
+/// stdin/stdout will ever reach a child program.
+///
+/// # Errors
+/// Will return an error if the system is out of file descriptors.
+pub fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds: [RawFd; 2] = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Wait for a process and convert how it exited into a [`ReturnCode`].
+///
+/// A process killed by a signal returns `128 + signal` like every other shell.
+pub fn wait_pid(pid: libc::pid_t) -> ReturnCode {
+    let mut status = 0;
+    loop {
+        if unsafe { libc::waitpid(pid, &mut status, 0) } != -1 {
+            break;
+        }
+        // Try again if we got interrupted, otherwise the child is already gone.
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return ReturnCode::FAILURE.into();
+        }
+    }
+    if libc::WIFEXITED(status) {
+        libc::WEXITSTATUS(status).into()
+    } else if libc::WIFSIGNALED(status) {
+        (128 + libc::WTERMSIG(status)).into()
+    } else {
+        ReturnCode::FAILURE.into()
+    }
+}
+
+/// Run a list of [`Actions`] as a pipeline.
+///
+/// Every stage is run in its own forked subshell, with its stdout connected to the stdin of the
+/// next stage, which is what lets builtins and functions be used just like external commands:
+///
+/// ```bash
+/// foo() { echo "bar"; }
+/// foo | tr 'a-z' 'A-Z'
+/// ```
+///
+/// # Returns
+/// The return code of the last stage of the pipeline, according to
+/// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_09_02>.
+pub fn execute_pipeline(
+    stages: Vec<Actions>,
+    variables: &mut Variables,
+    commands: &mut Commands,
+    global_env: &mut Env,
+) -> ReturnCode {
+    let mut children = vec![];
+    let mut previous_read: Option<OwnedFd> = None;
+    let stage_count = stages.len();
+
+    // Anything that is still buffered would otherwise get printed by every child.
+    let _ = io::stdout().flush();
+
+    for (idx, stage) in stages.into_iter().enumerate() {
+        let (next_read, write) = if idx + 1 == stage_count {
+            (None, None)
+        } else {
+            match pipe() {
+                Ok((read, write)) => (Some(read), Some(write)),
+                Err(oops) => {
+                    eprintln!("elvi: {oops}");
+                    break;
+                }
+            }
+        };
+
+        match unsafe { libc::fork() } {
+            -1 => {
+                eprintln!("elvi: {}", io::Error::last_os_error());
+                break;
+            }
+            0 => {
+                // We are the child now, so hook up our end of the pipes and run.
+                unsafe {
+                    // Rust ignores SIGPIPE, but a stage that writes into a pipe nobody reads from
+                    // anymore should die just like any other program.
+                    libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+                    if let Some(ref read) = previous_read {
+                        libc::dup2(read.as_raw_fd(), libc::STDIN_FILENO);
+                    }
+                    if let Some(ref write) = write {
+                        libc::dup2(write.as_raw_fd(), libc::STDOUT_FILENO);
+                    }
+                }
+                drop(previous_read);
+                drop(next_read);
+                drop(write);
+                let ret = eval(stage, variables, commands, global_env);
+                let _ = io::stdout().flush();
+                unsafe { libc::_exit(ret.cap().into()) }
+            }
+            pid => children.push(pid),
+        }
+
+        // The parent never uses the write end, and the read end belongs to the next stage.
+        drop(write);
+        previous_read = next_read;
+    }
+
+    let mut ret: ReturnCode = ReturnCode::FAILURE.into();
+    for pid in children {
+        ret = wait_pid(pid);
+    }
+    ret
+}
+
+/// Run an action in a forked subshell without waiting for it, like `sleep 5 &`.
+///
+/// The process ID is saved in `$!` and remembered so that `wait` can get its return code later.
+/// Since there is no job control, stdin is redirected from `/dev/null` and interrupts are ignored,
+/// according to
+/// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_09_03_02>.
+///
+/// # Returns
+/// Success, unless the subshell couldn't be started.
+pub fn execute_background(
+    action: Actions,
+    variables: &mut Variables,
+    commands: &mut Commands,
+    global_env: &mut Env,
+) -> ReturnCode {
+    // Anything that is still buffered would otherwise get printed by the child too.
+    let _ = io::stdout().flush();
+
+    match unsafe { libc::fork() } {
+        -1 => {
+            eprintln!("elvi: {}", io::Error::last_os_error());
+            ReturnCode::FAILURE.into()
+        }
+        0 => {
+            unsafe {
+                libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+                libc::signal(libc::SIGINT, libc::SIG_IGN);
+                libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+            }
+            // Explicit redirections are applied later, so they still win over this.
+            if let Ok(null) = File::open("/dev/null") {
+                unsafe { libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) };
+            }
+            // Jobs of the parent aren't ours to wait for.
+            global_env.jobs.clear();
+            let ret = eval(action, variables, commands, global_env);
+            let _ = io::stdout().flush();
+            unsafe { libc::_exit(ret.cap().into()) }
+        }
+        pid => {
+            global_env.jobs.push(pid);
+            variables.set_special("!", ElviType::String(pid.to_string()));
+            ReturnCode::SUCCESS.into()
+        }
+    }
+}
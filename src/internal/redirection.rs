@@ -0,0 +1,260 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::path::PathBuf;
+use std::thread;
+
+use super::errors::{describe_io_error, RedirectionError};
+use super::pipeline::pipe;
+use super::tree::{Redirection, RedirectionKind};
+use super::variables::{
+    backtick_end, brace_end, substitution_end, ElviType, Substitution, Variables,
+};
+
+/// The lowest file descriptor Elvi will use to keep copies of redirected file descriptors.
+///
+/// POSIX reserves everything from 10 onwards for the shell.
+const SAVED_FD_MINIMUM: RawFd = 10;
+
+/// A file descriptor that was redirected, and what it pointed to before.
+pub struct SavedFd {
+    /// The file descriptor that was redirected.
+    fd: RawFd,
+    /// A copy of the original file descriptor, or [`None`] if it was closed.
+    original: Option<OwnedFd>,
+}
+
+/// Apply a list of redirections to the current process, from left to right.
+///
+/// # Notes
+/// Every redirection is undone by passing the returned list to [`restore_redirections`].
+///
+/// # Errors
+/// Will return [`RedirectionError`] if a file cannot be opened or a file descriptor is not open.
+/// Any redirections already applied are undone before returning.
+pub fn apply_redirections(
+    redirections: &[Redirection],
+    variables: &Variables,
+) -> Result<Vec<SavedFd>, RedirectionError> {
+    // Anything buffered belongs to whatever stdout was before.
+    let _ = io::stdout().flush();
+    let mut saved = vec![];
+    for redirection in redirections {
+        if let Err(oops) = apply_redirection(redirection, variables, &mut saved) {
+            restore_redirections(saved);
+            return Err(oops);
+        }
+    }
+    Ok(saved)
+}
+
+/// Undo redirections made by [`apply_redirections`].
+pub fn restore_redirections(saved: Vec<SavedFd>) {
+    let _ = io::stdout().flush();
+    for entry in saved.into_iter().rev() {
+        match entry.original {
+            Some(original) => unsafe {
+                libc::dup2(original.as_raw_fd(), entry.fd);
+            },
+            None => unsafe {
+                libc::close(entry.fd);
+            },
+        }
+    }
+}
+
+fn apply_redirection(
+    redirection: &Redirection,
+    variables: &Variables,
+    saved: &mut Vec<SavedFd>,
+) -> Result<(), RedirectionError> {
+    if let RedirectionKind::HereDocument { .. } = redirection.kind {
+        let body = match &redirection.target {
+            ElviType::VariableSubstitution(body) => {
+                expand_here_document(body, variables, &mut |_| None)
+            }
+            literal => literal.to_string(),
+        };
+        let read = here_document(body).map_err(|oops| RedirectionError::HereDocument {
+            reason: describe_io_error(&oops),
+        })?;
+        save_fd(redirection.fd, saved);
+        move_fd(read, redirection.fd);
+        return Ok(());
+    }
+
+    let target = redirection
+        .target
+        .tilde_expansion(variables)
+        .eval_variables(variables)
+        .to_string();
+    let fd = redirection.fd;
+
+    match redirection.kind {
+        RedirectionKind::DuplicateInput | RedirectionKind::DuplicateOutput => {
+            // `>&-` and `<&-` close the file descriptor.
+            if target == "-" {
+                save_fd(fd, saved);
+                unsafe { libc::close(fd) };
+                return Ok(());
+            }
+            let Ok(source) = target.parse::<RawFd>() else {
+                return Err(RedirectionError::BadFd { fd: target });
+            };
+            if unsafe { libc::fcntl(source, libc::F_GETFD) } == -1 {
+                return Err(RedirectionError::BadFd { fd: target });
+            }
+            save_fd(fd, saved);
+            if source != fd {
+                unsafe { libc::dup2(source, fd) };
+            }
+        }
+        RedirectionKind::HereDocument { .. } => unreachable!("Here-documents are handled above"),
+        kind => {
+            // Relative paths are opened from the working directory, which `$PWD` can't be trusted
+            // to match, since scripts can change it.
+            let path = PathBuf::from(&target);
+            let mut options = OpenOptions::new();
+            match kind {
+                RedirectionKind::Input => options.read(true),
+                RedirectionKind::Append => options.append(true).create(true),
+                RedirectionKind::ReadWrite => options.read(true).write(true).create(true),
+                // With `-C`, only `>|` can overwrite a regular file, but something like
+                // `/dev/null` is fine.
+                RedirectionKind::Output if variables.has_flag('C') && path.is_file() => {
+                    options.write(true).create_new(true)
+                }
+                _ => options.write(true).create(true).truncate(true),
+            };
+            let file = options
+                .open(path)
+                .map_err(|oops| RedirectionError::CannotOpen {
+                    path: target,
+                    reason: describe_io_error(&oops),
+                })?;
+            save_fd(fd, saved);
+            move_fd(file.into(), fd);
+        }
+    }
+    Ok(())
+}
+
+/// Remember what `fd` points to before it gets redirected, unless that is already known.
+fn save_fd(fd: RawFd, saved: &mut Vec<SavedFd>) {
+    // We might be about to clobber one of our own copies, so move it out of the way.
+    for entry in saved.iter_mut() {
+        if entry.original.as_ref().map(AsRawFd::as_raw_fd) == Some(fd) {
+            entry.original = entry.original.take().and_then(|o| duplicate(o.as_raw_fd()));
+        }
+    }
+    if saved.iter().any(|entry| entry.fd == fd) {
+        return;
+    }
+    saved.push(SavedFd {
+        fd,
+        original: duplicate(fd),
+    });
+}
+
+/// Copy a file descriptor somewhere out of the way, or [`None`] if it is not open.
+fn duplicate(fd: RawFd) -> Option<OwnedFd> {
+    let copy = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, SAVED_FD_MINIMUM) };
+    if copy == -1 {
+        None
+    } else {
+        Some(unsafe { OwnedFd::from_raw_fd(copy) })
+    }
+}
+
+/// Make `fd` point to the same file as `file`, consuming it.
+fn move_fd(file: OwnedFd, fd: RawFd) {
+    if file.as_raw_fd() == fd {
+        // We got lucky and it was opened right where we wanted it, but it has to be inherited by
+        // programs we run.
+        let raw = file.into_raw_fd();
+        unsafe { libc::fcntl(raw, libc::F_SETFD, 0) };
+    } else {
+        unsafe { libc::dup2(file.as_raw_fd(), fd) };
+    }
+}
+
+/// Create a pipe with `body` written into it, returning the end to read from.
+///
+/// Bodies that fit in the pipe are written right away, bigger ones are written from another thread
+/// as they are read, so that we don't block on a full pipe.
+fn here_document(body: String) -> io::Result<OwnedFd> {
+    let (read, write) = pipe()?;
+    let mut write = File::from(write);
+    if body.len() <= libc::PIPE_BUF {
+        write.write_all(body.as_bytes())?;
+    } else {
+        thread::spawn(move || {
+            // Whoever reads the here-document is allowed to stop early.
+            let _ = write.write_all(body.as_bytes());
+        });
+    }
+    Ok(read)
+}
+
+/// Expand the body of a here-document with an unquoted delimiter.
+///
+/// Backslashes are only special before `$`, `` ` ``, `\` and a newline, so everything in between
+/// those is expanded a line at a time with [`ElviType::eval_variables_with`], which is given
+/// `substitute` to run command substitutions. A substitution or `${...}` can span several lines,
+/// so it is kept whole until the line it ends on.
+pub fn expand_here_document(
+    body: &str,
+    variables: &Variables,
+    substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+) -> String {
+    let mut expanded = String::new();
+    let mut pending = String::new();
+    let chars: Vec<char> = body.chars().collect();
+    let mut idx = 0;
+    while let Some(&charp) = chars.get(idx) {
+        let next = chars.get(idx + 1).copied();
+        match (charp, next) {
+            // A line continuation.
+            ('\\', Some('\n')) => idx += 1,
+            ('\\', Some('$' | '`' | '\\' | '"')) | ('\n', _) => {
+                expanded.push_str(
+                    &ElviType::VariableSubstitution(mem::take(&mut pending))
+                        .eval_variables_with(variables, substitute)
+                        .to_string(),
+                );
+                // Unlike in double quotes, a backslash before a `"` is kept.
+                if charp == '\\' && next == Some('"') {
+                    expanded.push(charp);
+                }
+                if charp == '\n' {
+                    expanded.push(charp);
+                } else {
+                    expanded.extend(next);
+                    idx += 1;
+                }
+            }
+            ('$', Some('(' | '{')) | ('`', _) => {
+                let end = if charp == '`' {
+                    backtick_end(&chars[idx + 1..]).map(|end| end + 1)
+                } else if next == Some('(') {
+                    substitution_end(&chars[idx + 2..]).map(|end| end + 2)
+                } else {
+                    brace_end(&chars[idx + 2..]).map(|end| end + 2)
+                };
+                // One that never closes is left for the expansion to complain about.
+                let end = idx + end.unwrap_or(0);
+                pending.extend(&chars[idx..=end]);
+                idx = end;
+            }
+            _ => pending.push(charp),
+        }
+        idx += 1;
+    }
+    expanded.push_str(
+        &ElviType::VariableSubstitution(pending)
+            .eval_variables_with(variables, substitute)
+            .to_string(),
+    );
+    expanded
+}
@@ -1,3 +1,5 @@
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
 use std::str::FromStr;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -130,6 +132,17 @@ impl From<i32> for ReturnCode {
     }
 }
 
+impl From<ExitStatus> for ReturnCode {
+    /// A process killed by a signal returns `128 + signal`.
+    fn from(value: ExitStatus) -> Self {
+        match (value.code(), value.signal()) {
+            (Some(code), _) => code.into(),
+            (None, Some(signal)) => (128 + signal).into(),
+            (None, None) => Self { val: Self::FAILURE },
+        }
+    }
+}
+
 impl std::ops::Not for ReturnCode {
     type Output = Self;
 
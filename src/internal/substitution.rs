@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::fd::AsRawFd;
+
+use crate::parse::grammar::eval_code;
+
+use super::arithmetic::evaluate;
+use super::commands::Commands;
+use super::env::{ControlFlow, Env};
+use super::errors::ElviError;
+use super::pipeline::{pipe, wait_pid};
+use super::redirection::expand_here_document;
+use super::status::ReturnCode;
+use super::tree::{Redirection, RedirectionKind};
+use super::variables::{ElviType, Substitution, Variable, Variables};
+
+/// Run the body of a command substitution, returning what it printed and its return code.
+///
+/// The body is parsed and run as an Elvi program in a forked subshell, so it can use anything a
+/// script can, while nothing it changes leaks back out:
+///
+/// ```bash
+/// greet() { echo "hello $1"; }
+/// x=`greet world | tr 'a-z' 'A-Z'`
+/// ```
+///
+/// # Notes
+/// Trailing newlines are removed from the output, conforming to
+/// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_06_03>.
+pub fn command_substitution(
+    body: &str,
+    variables: &Variables,
+    commands: &Commands,
+    env: &mut Env,
+) -> (String, ReturnCode) {
+    let (read, write) = match pipe() {
+        Ok(yay) => yay,
+        Err(oops) => {
+            eprintln!("elvi: {oops}");
+            return (String::new(), ReturnCode::FAILURE.into());
+        }
+    };
+
+    // Anything that is still buffered would otherwise get printed by the child too.
+    let _ = io::stdout().flush();
+
+    let pid = match unsafe { libc::fork() } {
+        -1 => {
+            eprintln!("elvi: {}", io::Error::last_os_error());
+            return (String::new(), ReturnCode::FAILURE.into());
+        }
+        0 => {
+            unsafe {
+                libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+                libc::dup2(write.as_raw_fd(), libc::STDOUT_FILENO);
+            }
+            drop(read);
+            drop(write);
+            let mut variables = variables.clone();
+            let mut commands = commands.clone();
+            let ret = eval_code(body, &mut variables, &mut commands, env);
+            let _ = io::stdout().flush();
+            unsafe { libc::_exit(ret.cap().into()) }
+        }
+        pid => pid,
+    };
+
+    drop(write);
+    let mut output = vec![];
+    if let Err(oops) = File::from(read).read_to_end(&mut output) {
+        eprintln!("elvi: {oops}");
+    }
+    let ret = wait_pid(pid);
+
+    let output = String::from_utf8_lossy(&output);
+    (output.trim_end_matches('\n').to_string(), ret)
+}
+
+#[derive(Default)]
+/// What running the substitutions in a word did, other than expanding it.
+pub struct SideEffects {
+    /// The return code of the last command substitution.
+    pub status: Option<ReturnCode>,
+    /// Variables assigned by arithmetic expansions and `${name:=word}`, which are only set by
+    /// [`SideEffects::apply`].
+    pub assigned: HashMap<String, String>,
+    /// The return code of the first expansion that failed, if one did.
+    pub failed: Option<ReturnCode>,
+}
+
+impl SideEffects {
+    /// Print why an expansion or assignment failed, unless an earlier one already did.
+    pub fn fail(&mut self, oops: &(impl ElviError + Display)) {
+        if self.failed.is_none() {
+            eprintln!("{oops}");
+            self.failed = Some(oops.ret());
+        }
+    }
+
+    /// Set every variable that was assigned while expanding.
+    ///
+    /// If anything failed, the shell has to exit instead, so [`ControlFlow::Exit`] is set with its
+    /// return code. Since subshells aren't forked, this only leaves the one it is in.
+    ///
+    /// # Returns
+    /// The return code of the last command substitution, if there were any.
+    pub fn apply(mut self, variables: &mut Variables, env: &mut Env) -> Option<ReturnCode> {
+        for (name, value) in mem::take(&mut self.assigned) {
+            let contents = ElviType::String(value);
+            let var = match variables.get_variable(&name) {
+                Some(template) => Variable {
+                    contents,
+                    ..template.clone()
+                },
+                None => contents.into(),
+            };
+            // Same as any other assignment, a non-interactive shell has to exit if it fails.
+            if let Err(oops) = variables.set_variable(name, var) {
+                self.fail(&oops);
+            }
+        }
+        if let Some(ret) = self.failed {
+            variables.set_ret(ret);
+            env.flow = Some(ControlFlow::Exit);
+        }
+        self.status
+    }
+}
+
+/// Expand a word into a single string, running its command substitutions and arithmetic
+/// expansions, for words that are never split into fields, like the target of a redirection.
+///
+/// # Returns
+/// The return code of the last command substitution run, if there were any.
+pub fn expand_substitutions(
+    word: &mut ElviType,
+    variables: &mut Variables,
+    commands: &Commands,
+    env: &mut Env,
+) -> Option<ReturnCode> {
+    let mut effects = SideEffects::default();
+    *word = word.tilde_expansion(variables).eval_variables_with(
+        variables,
+        &mut substitute(variables, commands, env, &mut effects),
+    );
+    effects.apply(variables, env)
+}
+
+/// Expand a word into the fields it makes up, running its substitutions, see
+/// [`ElviType::expand_fields`].
+///
+/// # Returns
+/// The fields, and the return code of the last command substitution run, if there were any.
+pub fn expand_word(
+    word: &ElviType,
+    variables: &mut Variables,
+    commands: &Commands,
+    env: &mut Env,
+) -> (Vec<String>, Option<ReturnCode>) {
+    let mut effects = SideEffects::default();
+    let fields = word.tilde_expansion(variables).expand_fields(
+        variables,
+        &mut substitute(variables, commands, env, &mut effects),
+    );
+    (fields, effects.apply(variables, env))
+}
+
+/// Run the substitutions in the target of a redirection, or in the body of a here-document.
+pub fn expand_redirection(
+    redirection: &mut Redirection,
+    variables: &mut Variables,
+    commands: &Commands,
+    env: &mut Env,
+) {
+    match (&redirection.kind, &redirection.target) {
+        (RedirectionKind::HereDocument { .. }, ElviType::VariableSubstitution(body)) => {
+            let mut effects = SideEffects::default();
+            redirection.target = ElviType::String(expand_here_document(
+                body,
+                variables,
+                &mut substitute(variables, commands, env, &mut effects),
+            ));
+            effects.apply(variables, env);
+        }
+        (RedirectionKind::HereDocument { .. }, _) => {}
+        _ => {
+            expand_substitutions(&mut redirection.target, variables, commands, env);
+        }
+    }
+}
+
+/// Create a function for [`ElviType::eval_variables_with`] that runs command substitutions,
+/// evaluates arithmetic expansions and assigns variables, keeping track of what they did in
+/// `effects`.
+///
+/// # Notes
+/// An expansion that fails, like an arithmetic expansion dividing by zero, exits the shell as
+/// required by
+/// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_08_01>, once
+/// [`SideEffects::apply`] is called. Nothing else in the word is run after that.
+pub fn substitute<'a>(
+    variables: &'a Variables,
+    commands: &'a Commands,
+    env: &'a mut Env,
+    effects: &'a mut SideEffects,
+) -> impl FnMut(Substitution) -> Option<String> + 'a {
+    move |substitution| match substitution {
+        Substitution::Command(_) | Substitution::Arithmetic(_) if effects.failed.is_some() => {
+            Some(String::new())
+        }
+        Substitution::Command(body) => {
+            let (output, ret) = command_substitution(body, variables, commands, env);
+            effects.status = Some(ret);
+            Some(output)
+        }
+        Substitution::Arithmetic(expression) => {
+            match evaluate(expression, variables, &mut effects.assigned) {
+                Ok(value) => Some(value.to_string()),
+                Err(oops) => {
+                    effects.fail(&oops);
+                    Some(String::new())
+                }
+            }
+        }
+        Substitution::Assignment { name, value } => {
+            effects.assigned.insert(name.into(), value.into());
+            None
+        }
+        Substitution::Parameter(name) => effects.assigned.get(name).cloned(),
+        Substitution::Failed(oops) => {
+            effects.fail(oops);
+            None
+        }
+    }
+}
@@ -1,14 +1,8 @@
-use std::io;
-use std::io::Write;
-
-use crate::internal::errors::ElviError;
-use crate::internal::status::ReturnCode;
-
-use super::commands::execute_external_command;
 use super::env::Env;
 use super::errors::VariableError;
+use super::substitution::{substitute, SideEffects};
 use super::{
-    commands::{Commands, ExternalCommand},
+    commands::Commands,
     variables::{ElviGlobal, ElviType, Variable, Variables},
 };
 
@@ -25,14 +19,36 @@ pub enum Actions {
     IfStatement(Box<Conditional>),
     /// For loop
     ForLoop(Loop),
+    /// While loop
+    WhileLoop(ConditionalLoop),
+    /// Until loop
+    UntilLoop(ConditionalLoop),
+    /// Case statement
+    CaseStatement(Case),
     /// Function declaration.
     FunctionDeclaration(Function),
     /// Subshell
     Subshell(Vec<Actions>),
     /// Grouped commmands
     CompoundBrackets(Vec<Actions>),
+    /// Commands whose output is fed into the next command.
+    Pipeline(Vec<Actions>),
+    /// Run an action with its file descriptors redirected.
+    Redirect(Box<Actions>, Vec<Redirection>),
+    /// Run the second action only if the first one succeeds.
+    And(Box<Actions>, Box<Actions>),
+    /// Run the second action only if the first one fails.
+    Or(Box<Actions>, Box<Actions>),
+    /// Invert the return code of an action.
+    Not(Box<Actions>),
     /// Do nothing.
     Null,
+    /// A statement, along with the line it starts on, which `$LINENO` is set to while it runs.
+    Statement(usize, Box<Actions>),
+    /// Run a simple command with variables assigned in front of it, e.g.: `LANG=C sort file`.
+    Assigned(Vec<(String, Variable)>, Box<Actions>),
+    /// Run an action in the background, without waiting for it to finish.
+    Background(Box<Actions>),
 }
 
 #[derive(Debug, Clone)]
@@ -51,8 +67,15 @@ pub enum Builtins {
     Dbg(Option<Vec<ElviType>>),
     /// Just needs a variable name.
     Unset(Option<Vec<ElviType>>),
-    /// Will exit with `0` if not given data, and if so, attempt to parse into a number.
+    /// Will exit with the last return code if not given data, and if so, attempt to parse into a
+    /// number.
     Exit(Option<Vec<ElviType>>),
+    /// Can take nothing or a number of loops.
+    Break(Option<Vec<ElviType>>),
+    /// Can take nothing or a number of loops.
+    Continue(Option<Vec<ElviType>>),
+    /// Can take nothing or a return code.
+    Return(Option<Vec<ElviType>>),
     /// Will display commands if no argument given, will regenerate if `-r` is passed.
     Hash(Option<Vec<ElviType>>),
     /// Needs a path, empty, or dash.
@@ -63,6 +86,102 @@ pub enum Builtins {
     Echo(Option<Vec<ElviType>>),
     /// Can take nothing or a number.
     Shift(Option<Vec<ElviType>>),
+    /// Can take nothing or a list of process IDs.
+    Wait(Option<Vec<ElviType>>),
+    /// Can take options to turn on or off, followed by new positional parameters.
+    Set(Option<Vec<ElviType>>),
+    /// Can take names and options, or assignments, which aren't split into fields.
+    Export(Option<Vec<ElviType>>, Vec<(String, Variable)>),
+    /// Same as [`Builtins::Export`], along with the line and column it's on.
+    Readonly(
+        Option<Vec<ElviType>>,
+        Vec<(String, Variable)>,
+        (usize, usize),
+    ),
+}
+
+impl Builtins {
+    /// Whether this is a special builtin, which keeps the variables assigned in front of it, as
+    /// listed in <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_14>.
+    #[must_use]
+    pub fn is_special(&self) -> bool {
+        matches!(
+            self,
+            Self::Exit(_)
+                | Self::Break(_)
+                | Self::Continue(_)
+                | Self::Return(_)
+                | Self::Shift(_)
+                | Self::Unset(_)
+                | Self::Set(_)
+                | Self::Export(..)
+                | Self::Readonly(..)
+        )
+    }
+
+    /// The name a builtin is run by.
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Dbg(_) => "dbg",
+            Self::Unset(_) => "unset",
+            Self::Exit(_) => "exit",
+            Self::Break(_) => "break",
+            Self::Continue(_) => "continue",
+            Self::Return(_) => "return",
+            Self::Hash(_) => "hash",
+            Self::Cd(_) => "cd",
+            Self::Test(..) => "test",
+            Self::Echo(_) => "echo",
+            Self::Shift(_) => "shift",
+            Self::Wait(_) => "wait",
+            Self::Set(_) => "set",
+            Self::Export(..) => "export",
+            Self::Readonly(..) => "readonly",
+        }
+    }
+
+    /// Get the list of words given to a builtin, for builtins that take a list of them.
+    pub fn arguments_mut(&mut self) -> Option<&mut Option<Vec<ElviType>>> {
+        match self {
+            Self::Dbg(words)
+            | Self::Unset(words)
+            | Self::Exit(words)
+            | Self::Break(words)
+            | Self::Continue(words)
+            | Self::Return(words)
+            | Self::Hash(words)
+            | Self::Cd(words)
+            | Self::Echo(words)
+            | Self::Shift(words)
+            | Self::Wait(words)
+            | Self::Set(words)
+            | Self::Export(words, _)
+            | Self::Readonly(words, ..) => Some(words),
+            Self::Test(..) => None,
+        }
+    }
+
+    /// Get every word given to a builtin.
+    pub fn words_mut(&mut self) -> Vec<&mut ElviType> {
+        match self {
+            Self::Dbg(words)
+            | Self::Unset(words)
+            | Self::Exit(words)
+            | Self::Break(words)
+            | Self::Continue(words)
+            | Self::Return(words)
+            | Self::Hash(words)
+            | Self::Cd(words)
+            | Self::Echo(words)
+            | Self::Shift(words)
+            | Self::Wait(words)
+            | Self::Set(words)
+            | Self::Export(words, _)
+            | Self::Readonly(words, ..) => words.iter_mut().flatten().collect(),
+            Self::Test(_, options) => options.words_mut(),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -83,12 +202,168 @@ pub struct Conditional {
 pub struct Loop {
     /// Variable to update
     pub variable: ElviType,
-    /// Vector of elements to loop over.
-    pub elements: Vec<ElviType>,
+    /// Vector of elements to loop over, or [`None`] to loop over the positional parameters, like
+    /// `for i; do` does.
+    pub elements: Option<Vec<ElviType>>,
     /// The resulting code that is executed every [`Loop::elements`].
     pub do_block: Vec<Actions>,
 }
 
+#[derive(Debug, Clone)]
+/// A struct for loops that run depending on a condition, like `while` and `until`.
+pub struct ConditionalLoop {
+    /// The list of actions whose return code decides if the loop keeps going.
+    pub condition: Vec<Actions>,
+    /// The resulting code that is executed every time the condition allows.
+    pub do_block: Vec<Actions>,
+}
+
+#[derive(Debug, Clone)]
+/// A struct for case statements.
+pub struct Case {
+    /// The word to match against every [`CaseItem`].
+    pub word: ElviType,
+    /// Every branch of the case statement, in order.
+    pub items: Vec<CaseItem>,
+}
+
+#[derive(Debug, Clone)]
+/// A single branch of a [`Case`].
+pub struct CaseItem {
+    /// The patterns of the branch, each one split into its quoted and unquoted parts.
+    pub patterns: Vec<Vec<ElviType>>,
+    /// The resulting code that is executed if any of [`CaseItem::patterns`] match.
+    pub body: Vec<Actions>,
+}
+
+#[derive(Debug, Clone)]
+/// A single redirection of a file descriptor.
+///
+/// According to <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_07>.
+pub struct Redirection {
+    /// File descriptor being redirected.
+    pub fd: i32,
+    /// How the file descriptor is being redirected.
+    pub kind: RedirectionKind,
+    /// The file, or file descriptor, to redirect to.
+    pub target: ElviType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+/// A list of ways a file descriptor can be redirected.
+pub enum RedirectionKind {
+    /// `[n]<file`
+    Input,
+    /// `[n]>file`
+    Output,
+    /// `[n]>|file`
+    Clobber,
+    /// `[n]>>file`
+    Append,
+    /// `[n]<&fd` & `[n]<&-`
+    DuplicateInput,
+    /// `[n]>&fd` & `[n]>&-`
+    DuplicateOutput,
+    /// `[n]<>file`
+    ReadWrite,
+    /// `[n]<<delimiter` & `[n]<<-delimiter`
+    ///
+    /// Until the body is read, [`Redirection::target`] holds the delimiter.
+    HereDocument {
+        /// Whether leading tabs are removed from every line, from `<<-`.
+        strip_tabs: bool,
+    },
+}
+
+impl RedirectionKind {
+    /// The file descriptor that is redirected when one is not given.
+    #[must_use]
+    pub fn default_fd(self) -> i32 {
+        match self {
+            Self::Input | Self::DuplicateInput | Self::ReadWrite | Self::HereDocument { .. } => {
+                libc::STDIN_FILENO
+            }
+            Self::Output | Self::Clobber | Self::Append | Self::DuplicateOutput => {
+                libc::STDOUT_FILENO
+            }
+        }
+    }
+}
+
+impl Actions {
+    /// Wrap an action in [`Actions::Redirect`], merging with any redirections it already has.
+    ///
+    /// Redirections in `before` are applied first, then the ones already on the action, then
+    /// the ones in `after`.
+    #[must_use]
+    pub fn redirect(self, mut before: Vec<Redirection>, mut after: Vec<Redirection>) -> Self {
+        if before.is_empty() && after.is_empty() {
+            return self;
+        }
+        match self {
+            Self::Redirect(inner, mut middle) => {
+                before.append(&mut middle);
+                before.append(&mut after);
+                Self::Redirect(inner, before)
+            }
+            other => {
+                before.append(&mut after);
+                Self::Redirect(Box::new(other), before)
+            }
+        }
+    }
+
+    /// Whether variables assigned in front of this action stay assigned after it runs, which is
+    /// the case for special builtins and when there is no command at all.
+    #[must_use]
+    pub fn keeps_assignments(&self) -> bool {
+        match self {
+            Self::Builtin(built) => built.is_special(),
+            Self::Redirect(inner, _) => inner.keeps_assignments(),
+            Self::Null => true,
+            _ => false,
+        }
+    }
+
+    /// Get every here-document of a statement, in the order they were written.
+    ///
+    /// # Notes
+    /// Compound commands are not looked inside of, because the statements in them have already
+    /// read their own here-documents.
+    pub fn here_documents_mut(&mut self) -> Vec<&mut Redirection> {
+        match self {
+            Self::Pipeline(stages) => stages
+                .iter_mut()
+                .flat_map(Self::here_documents_mut)
+                .collect(),
+            Self::Redirect(inner, redirections) => {
+                let mut docs = inner.here_documents_mut();
+                docs.extend(redirections.iter_mut().filter(|redirection| {
+                    matches!(redirection.kind, RedirectionKind::HereDocument { .. })
+                }));
+                docs
+            }
+            Self::And(left, right) | Self::Or(left, right) => {
+                let mut docs = left.here_documents_mut();
+                docs.append(&mut right.here_documents_mut());
+                docs
+            }
+            Self::Not(inner)
+            | Self::Statement(_, inner)
+            | Self::Background(inner)
+            | Self::Assigned(_, inner) => inner.here_documents_mut(),
+            Self::FunctionDeclaration(Function {
+                contents: Some(contents),
+                ..
+            }) => contents
+                .iter_mut()
+                .flat_map(Self::here_documents_mut)
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 /// A list of things `test` can do.
 pub enum TestOptions {
@@ -162,6 +437,91 @@ pub enum TestOptions {
     Int1LessEqualInt2Algebraically((ElviType, ElviType)),
 }
 
+impl TestOptions {
+    /// The operator that was given to `test`, which goes between the words if there are two of
+    /// them, or [`None`] if there was only a string.
+    #[must_use]
+    pub fn operator(&self) -> Option<&'static str> {
+        let operator = match self {
+            Self::BlockFileExists(_) => "-b",
+            Self::CharacterFileExists(_) => "-c",
+            Self::DirectoryExists(_) => "-d",
+            Self::AnyFileExists(_) => "-e",
+            Self::RegularFileExists(_) => "-f",
+            Self::GroupIDFlagSetExists(_) => "-g",
+            Self::SymbolicLinkExists(_) => "-h",
+            Self::StickyBitSetExists(_) => "-k",
+            Self::StringNonZero(_) => "-n",
+            Self::NamedPipeExists(_) => "-p",
+            Self::ReadableFileExists(_) => "-r",
+            Self::FileExistsGreaterThanZero(_) => "-s",
+            Self::FDDescriptorNumberOpened(_) => "-t",
+            Self::FileExistsUserIDSet(_) => "-u",
+            Self::FileExistsWritable(_) => "-w",
+            Self::FileExistsExecutable(_) => "-x",
+            Self::StringZero(_) => "-z",
+            Self::FileExistsOwnerEffectiveUserID(_) => "-O",
+            Self::FileExistsOwnerEffectiveGroupID(_) => "-G",
+            Self::FileExistsSocket(_) => "-S",
+            Self::File1NewerThanFile2(_) => "-nt",
+            Self::File1OlderThanFile2(_) => "-ot",
+            Self::File1SameAsFile2(_) => "-ef",
+            Self::StringNotNull(_) => return None,
+            Self::String1IsString2(_) => "=",
+            Self::String1IsNotString2(_) => "!=",
+            Self::String1BeforeString2ASCII(_) => "<",
+            Self::String1AfterString2ASCII(_) => ">",
+            Self::Int1EqualsInt2Algebraically(_) => "-eq",
+            Self::Int1NotEqualsInt2Algebraically(_) => "-ne",
+            Self::Int1GreaterThanInt2Algebraically(_) => "-gt",
+            Self::Int1LessThanInt2Algebraically(_) => "-lt",
+            Self::Int1GreaterEqualInt2Algebraically(_) => "-ge",
+            Self::Int1LessEqualInt2Algebraically(_) => "-le",
+        };
+        Some(operator)
+    }
+
+    /// Get every word given to `test`.
+    pub fn words_mut(&mut self) -> Vec<&mut ElviType> {
+        match self {
+            Self::BlockFileExists(word)
+            | Self::CharacterFileExists(word)
+            | Self::DirectoryExists(word)
+            | Self::AnyFileExists(word)
+            | Self::RegularFileExists(word)
+            | Self::GroupIDFlagSetExists(word)
+            | Self::SymbolicLinkExists(word)
+            | Self::StickyBitSetExists(word)
+            | Self::StringNonZero(word)
+            | Self::NamedPipeExists(word)
+            | Self::ReadableFileExists(word)
+            | Self::FileExistsGreaterThanZero(word)
+            | Self::FDDescriptorNumberOpened(word)
+            | Self::FileExistsUserIDSet(word)
+            | Self::FileExistsWritable(word)
+            | Self::FileExistsExecutable(word)
+            | Self::StringZero(word)
+            | Self::FileExistsOwnerEffectiveUserID(word)
+            | Self::FileExistsOwnerEffectiveGroupID(word)
+            | Self::FileExistsSocket(word)
+            | Self::StringNotNull(word) => vec![word],
+            Self::File1NewerThanFile2((left, right))
+            | Self::File1OlderThanFile2((left, right))
+            | Self::File1SameAsFile2((left, right))
+            | Self::String1IsString2((left, right))
+            | Self::String1IsNotString2((left, right))
+            | Self::String1BeforeString2ASCII((left, right))
+            | Self::String1AfterString2ASCII((left, right))
+            | Self::Int1EqualsInt2Algebraically((left, right))
+            | Self::Int1NotEqualsInt2Algebraically((left, right))
+            | Self::Int1GreaterThanInt2Algebraically((left, right))
+            | Self::Int1LessThanInt2Algebraically((left, right))
+            | Self::Int1GreaterEqualInt2Algebraically((left, right))
+            | Self::Int1LessEqualInt2Algebraically((left, right)) => vec![left, right],
+        }
+    }
+}
+
 /// Function to change/assign a variable.
 ///
 /// I added this because putting everything into `grammar.rs` was too much work and tedious.
@@ -174,10 +534,31 @@ pub fn change_variable(
 ) {
     // Makes shit easier to deal with.
     match &var.contents {
-        goopy @ ElviType::VariableSubstitution(_) => {
+        goopy @ (ElviType::VariableSubstitution(_)
+        | ElviType::BareString(_)
+        | ElviType::CommandSubstitution(_)
+        | ElviType::Word(_)) => {
             // Goopy will save us!!!
-            var.contents = goopy.eval_escapes().eval_variables(variables);
+            let mut effects = SideEffects::default();
+            var.contents = goopy
+                .assignment_tilde_expansion(variables)
+                .eval_variables_with(
+                    variables,
+                    &mut substitute(variables, commands, env, &mut effects),
+                );
+            let status = effects.apply(variables, env);
+            // Nothing is assigned if the expansion failed.
+            if env.flow.is_some() {
+                return;
+            }
             change_variable(variables, commands, env, name, var);
+            if env.flow.is_some() {
+                return;
+            }
+            // An assignment returns whatever the last command substitution in it did.
+            if let Some(status) = status {
+                variables.set_ret(status);
+            }
         }
         ElviType::String(_) => {
             // First let's get the level because while parsing we assume a certain variable level that is
@@ -186,9 +567,10 @@ pub fn change_variable(
                 var.shell_lvl = ElviGlobal::Normal(env.subshells_in);
             }
             if var.shell_lvl == ElviGlobal::Local && !env.in_function() {
-                let err = VariableError::NotInFunction { name: "local" };
-                eprintln!("{err}");
-                std::process::exit(err.ret().get().into());
+                let mut effects = SideEffects::default();
+                effects.fail(&VariableError::NotInFunction { name: "local" });
+                effects.apply(variables, env);
+                return;
             }
             match variables.set_variable(name, var.to_owned()) {
                 Ok(()) => {}
@@ -198,63 +580,10 @@ pub fn change_variable(
                 // but during a non-interactive shell, it must exact, and since Elvi is solely a
                 // non-interactive shell, that's what we'll do.
                 Err(oops) => {
-                    eprintln!("{oops}");
-                    std::process::exit(oops.ret().get().into());
-                }
-            }
-        }
-        ElviType::CommandSubstitution(x) => {
-            //TODO: Interpolate the variables if any
-            let cmd_to_run: ExternalCommand = x.into();
-            // Set variable to empty if we can't get the command
-            let retty = execute_external_command(cmd_to_run, variables, commands);
-            let mut child = match retty {
-                Ok(yay) => yay,
-                Err(oops) => {
-                    if let ElviGlobal::Normal { .. } = var.shell_lvl {
-                        var.shell_lvl = ElviGlobal::Normal(env.subshells_in);
-                    }
-                    match variables.set_variable(
-                        name,
-                        Variable {
-                            modification_status: var.modification_status,
-                            shell_lvl: var.shell_lvl,
-                            line: var.line,
-                            ..Default::default()
-                        },
-                    ) {
-                        Ok(()) => {}
-                        Err(oops) => eprintln!("{oops}"),
-                    }
-                    eprintln!("{oops}");
-                    return variables.set_ret(oops.ret());
-                }
-            };
-            let completed_child = match child.output() {
-                Ok(yay) => yay,
-                Err(f) => {
-                    eprintln!("{f}");
-                    return variables.set_ret(ReturnCode::FAILURE.into());
+                    let mut effects = SideEffects::default();
+                    effects.fail(&oops);
+                    effects.apply(variables, env);
                 }
-            };
-            if !completed_child.stderr.is_empty() {
-                io::stderr().write_all(&completed_child.stderr).unwrap();
-            }
-            var.contents = ElviType::String(
-                // POSIX says (somewhere trust me) that stderr shouldn't be in the variable
-                // assignment if it comes up.
-                std::str::from_utf8(&completed_child.stdout)
-                    .unwrap()
-                    // This is to conform to
-                    // <https://www.gnu.org/software/bash/manual/html_node/Command-Substitution.html>,
-                    // specifically the part about trailing newlines deleted. This is from
-                    // the bash manual but it is the same in POSIX, I've checked.
-                    .trim_end_matches('\n')
-                    .to_string(),
-            );
-            match variables.set_variable(name, var.clone()) {
-                Ok(()) => {}
-                Err(oops) => eprintln!("{oops}"),
             }
         }
         _ => unimplemented!("Give me a break please"),
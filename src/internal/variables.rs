@@ -1,17 +1,18 @@
 use core::fmt;
-use glob::glob;
 use homedir::get_home;
 use pest_consume::Itertools;
 use std::{
-    collections::HashMap,
-    env,
-    ffi::OsStr,
-    path::{Path, PathBuf},
-    process,
+    collections::{HashMap, HashSet},
+    env, fs, iter, mem,
+    os::unix::process,
+    path::Path,
 };
 
 use super::errors::VariableError;
+use super::fields::Fields;
+use super::pattern::Pattern;
 use super::status::ReturnCode;
+use crate::user_flags::Extension;
 
 /// Functions to describe the quoted nature of a type.
 pub trait QuotedNature {
@@ -44,11 +45,23 @@ pub enum ElviType {
     /// $borp
     /// ```
     BareString(String),
+    /// A word made of parts right next to each other which are quoted differently, where every
+    /// part is a [`ElviType::BareString`], [`ElviType::String`] or
+    /// [`ElviType::VariableSubstitution`].
+    ///
+    /// ```bash
+    /// --prefix="$HOME"/opt
+    /// "$dir"/*.txt
+    /// ```
+    Word(Vec<ElviType>),
 }
 
 impl QuotedNature for ElviType {
     fn is_quoted(&self) -> bool {
-        !matches!(self, Self::String(_) | Self::BareString(_))
+        match self {
+            Self::Word(parts) => parts.iter().any(Self::is_quoted),
+            _ => !matches!(self, Self::String(_) | Self::BareString(_)),
+        }
     }
 }
 
@@ -64,10 +77,8 @@ pub enum ElviMutable {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
-/// Enum representing the globality of a variable.
+/// Enum representing the scope of a variable.
 pub enum ElviGlobal {
-    /// Exported variable.
-    Global,
     /// Scoped variable.
     ///
     /// Every variable in [`Variables`] that is above the current level will be sweeped after the
@@ -84,6 +95,10 @@ pub struct Variables {
     pub vars: HashMap<String, Variable>,
     /// A separate field used solely for positional parameters.
     pub params: Vec<Variable>,
+    /// Names given to `export` that aren't set yet, which are exported as soon as they are.
+    pub exports: HashSet<String>,
+    /// Names given to `readonly` that aren't set, which never can be, and where that happened.
+    pub frozen: HashMap<String, (usize, usize)>,
 }
 
 #[derive(Debug, Clone)]
@@ -95,6 +110,8 @@ pub struct Variable {
     pub modification_status: ElviMutable,
     /// Relation to `$SHLVL`.
     pub shell_lvl: ElviGlobal,
+    /// Whether it's passed on to the environment of commands that are run.
+    pub exported: bool,
     /// Line and column variable was declared on.
     pub line: (usize, usize),
 }
@@ -115,17 +132,103 @@ impl Variables {
         }
     }
 
-    /// Return a hashmap of all variables marked as [`ElviGlobal::Global`] and their corresponding
-    /// values (can only be a [`ElviType::String`]).
+    /// Return a hashmap of all exported variables and their corresponding values (can only be a
+    /// [`ElviType::String`]).
     #[must_use]
     pub fn get_environmentals(&self) -> HashMap<String, String> {
         self.vars
             .iter()
-            .filter(|(_, var)| var.shell_lvl == ElviGlobal::Global)
+            .filter(|(_, var)| var.exported)
             .map(|(name, var)| (name.to_string(), var.contents.to_string()))
             .collect()
     }
 
+    /// Import the variables of the environment the shell was started with, which stay exported.
+    ///
+    /// If `only` is given, nothing else is imported, so an empty list is a clean environment.
+    /// `$IFS` is never imported, as allowed by
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_05_03>,
+    /// and neither is a `$PWD` that isn't the current directory.
+    pub fn import_environment(&mut self, only: Option<&[String]>) {
+        for (name, value) in env::vars_os() {
+            // Names and values that aren't UTF-8 couldn't be used by a script anyway.
+            let (Ok(name), Ok(value)) = (name.into_string(), value.into_string()) else {
+                continue;
+            };
+            if !is_name(&name)
+                || NOT_IMPORTED.contains(&name.as_str())
+                || only.is_some_and(|only| !only.contains(&name))
+                || (name == "PWD" && !is_current_dir(&value))
+            {
+                continue;
+            }
+            self.vars.insert(
+                name,
+                Variable {
+                    contents: ElviType::String(value),
+                    exported: true,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+
+    /// Set the variables the shell needs that weren't imported from the environment:
+    ///
+    /// * `PATH`, which is only used to find commands and isn't exported
+    /// * `PWD` (exported) and `OLDPWD`, from the current directory
+    ///
+    /// `HOME` is left alone, since the shell isn't the one to decide it, so `cd` without an
+    /// operand fails if it wasn't imported.
+    ///
+    /// Any of them that can't be found, like when the current directory was deleted or isn't
+    /// UTF-8, are left unset.
+    pub fn fill_defaults(&mut self) {
+        let pwd = env::current_dir()
+            .ok()
+            .and_then(|dir| dir.into_os_string().into_string().ok());
+        let defaults = [
+            ("PATH", Some(DEFAULT_PATH.to_string()), false),
+            ("PWD", pwd.clone(), true),
+            ("OLDPWD", pwd, false),
+        ];
+        for (name, value, exported) in defaults {
+            let Some(value) = value else {
+                continue;
+            };
+            self.vars.entry(name.into()).or_insert_with(|| Variable {
+                contents: ElviType::String(value),
+                exported,
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Make a variable readonly from the line and column given on, even if it isn't set.
+    pub fn freeze(&mut self, name: &str, line: (usize, usize)) {
+        match self.vars.get_mut(name) {
+            Some(var) => {
+                if let ElviMutable::Normal = var.modification_status {
+                    var.modification_status = ElviMutable::Readonly;
+                    var.line = line;
+                }
+            }
+            None => {
+                self.frozen.entry(name.into()).or_insert(line);
+            }
+        }
+    }
+
+    /// Mark a variable to be exported, even if it isn't set yet.
+    pub fn export(&mut self, name: &str) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.exported = true,
+            None => {
+                self.exports.insert(name.into());
+            }
+        }
+    }
+
     /// Unsets a variable.
     ///
     /// # Notes
@@ -136,6 +239,7 @@ impl Variables {
     /// 1. `Some(())` for a variable that was found and removed.
     /// 2. `None` for a variable that was not found.
     pub fn unset(&mut self, var: &str) -> Option<()> {
+        self.exports.remove(var);
         match self.vars.remove(var) {
             Some(_) => Some(()),
             None => None,
@@ -163,6 +267,53 @@ impl Variables {
         );
     }
 
+    /// Set a special parameter, like `$$` or `$!`, which scripts can't assign themselves.
+    pub fn set_special(&mut self, name: &str, contents: ElviType) {
+        self.vars.insert(
+            name.into(),
+            Variable {
+                contents,
+                modification_status: ElviMutable::ReadonlyUnsettable,
+                shell_lvl: ElviGlobal::Normal(1),
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Whether an option like `-f` is turned on, which are the letters of `$-`.
+    #[must_use]
+    pub fn has_flag(&self, flag: char) -> bool {
+        self.get_variable("-")
+            .is_some_and(|flags| flags.contents.to_string().contains(flag))
+    }
+
+    /// Turn an option like `-f` on or off, updating `$-`.
+    pub fn set_flag(&mut self, flag: char, on: bool) {
+        let mut flags: String = self
+            .get_variable("-")
+            .map(|flags| flags.contents.to_string())
+            .unwrap_or_default()
+            .chars()
+            .filter(|charp| *charp != flag)
+            .collect();
+        if on {
+            flags.push(flag);
+        }
+        self.set_special("-", ElviType::String(flags));
+    }
+
+    /// Set `$LINENO`, which unlike [`Variables::set_variable`] leaves `$?` alone.
+    pub fn set_line(&mut self, line: usize) {
+        self.vars.insert(
+            "LINENO".into(),
+            Variable {
+                contents: ElviType::Number(line),
+                shell_lvl: ElviGlobal::Normal(1),
+                ..Default::default()
+            },
+        );
+    }
+
     /// Quick function to pull `$?`.
     ///
     /// # Panics
@@ -177,7 +328,8 @@ impl Variables {
             .clone()
     }
 
-    /// Set a given variable.
+    /// Set a given variable, which stays exported if it already was, or gets exported if `-a` is
+    /// on.
     ///
     /// # Errors
     /// Will return [`VariableError`] if a variable is [`ElviMutable::Readonly`] or
@@ -188,7 +340,8 @@ impl Variables {
         var: impl Into<Variable>,
     ) -> Result<(), VariableError> {
         let name_two = name.into();
-        let var_two = var.into();
+        let mut var_two = var.into();
+        var_two.exported |= self.has_flag('a');
         if let Some(value) = self.vars.get(&name_two) {
             match value.modification_status {
                 ElviMutable::Readonly | ElviMutable::ReadonlyUnsettable => {
@@ -198,13 +351,20 @@ impl Variables {
                     })
                 }
                 ElviMutable::Normal => {
+                    var_two.exported |= value.exported;
                     self.set_ret(ReturnCode::ret(ReturnCode::SUCCESS));
                     self.vars.insert(name_two, var_two);
                     Ok(())
                 }
             }
+        } else if let Some(lines) = self.frozen.get(&name_two) {
+            Err(VariableError::Readonly {
+                name: name_two,
+                lines: *lines,
+            })
         // Is this a fresh variable?
         } else {
+            var_two.exported |= self.exports.remove(&name_two);
             self.set_ret(ReturnCode::ret(ReturnCode::SUCCESS));
             self.vars.insert(name_two, var_two);
             Ok(())
@@ -238,15 +398,18 @@ impl Variables {
 }
 
 impl Default for Variables {
-    /// Create new default variable list with required variables:
+    /// Create new default variable list with the variables the shell always sets itself:
     ///
-    /// * `PS1`
+    /// * `PS1`, `PS2` and `PS4`
     /// * `IFS`
-    /// * `PATH`
     /// * `?`
-    /// * `PWD`
-    /// * `OLDPWD`
-    /// * `HOME`
+    /// * `ELVI_VERSION`
+    /// * `SHLVL`
+    /// * `PPID` and `OPTIND`, which are always set when the shell starts according to
+    ///   <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_05_03>
+    ///
+    /// The rest come from [`Variables::import_environment`], and whatever is still missing after
+    /// that from [`Variables::fill_defaults`].
     fn default() -> Self {
         Self {
             vars: HashMap::from([
@@ -258,73 +421,67 @@ impl Default for Variables {
                     },
                 ),
                 (
-                    "IFS".into(),
+                    "PS2".into(),
                     Variable {
-                        contents: ElviType::String(r" \t\n".into()),
+                        contents: ElviType::String("> ".into()),
                         ..Default::default()
                     },
                 ),
                 (
-                    "PATH".into(),
+                    "PS4".into(),
                     Variable {
-                        contents: ElviType::String("/usr/sbin:/usr/bin:/sbin:/bin".into()),
+                        contents: ElviType::String("+ ".into()),
                         ..Default::default()
                     },
                 ),
                 (
-                    "?".into(),
+                    "IFS".into(),
                     Variable {
-                        contents: ElviType::ErrExitCode(0),
-                        modification_status: ElviMutable::ReadonlyUnsettable,
+                        contents: ElviType::String(" \t\n".into()),
                         ..Default::default()
                     },
                 ),
                 (
-                    "PWD".into(),
+                    "?".into(),
                     Variable {
-                        contents: ElviType::String(
-                            env::current_dir().unwrap().to_str().unwrap().to_string(),
-                        ),
+                        contents: ElviType::ErrExitCode(0),
+                        modification_status: ElviMutable::ReadonlyUnsettable,
                         ..Default::default()
                     },
                 ),
                 (
-                    "OLDPWD".into(),
+                    "ELVI_VERSION".into(),
                     Variable {
-                        contents: ElviType::String(
-                            env::current_dir().unwrap().to_str().unwrap().to_string(),
-                        ),
+                        contents: ElviType::String(env!("CARGO_PKG_VERSION").to_string()),
                         ..Default::default()
                     },
                 ),
                 (
-                    "HOME".into(),
+                    "SHLVL".into(),
                     Variable {
-                        contents: ElviType::String(
-                            // We (I) don't support windows.
-                            #[allow(deprecated)]
-                            env::home_dir().unwrap().to_str().unwrap().to_string(),
-                        ),
+                        contents: ElviType::Number(1),
                         ..Default::default()
                     },
                 ),
                 (
-                    "ELVI_VERSION".into(),
+                    "PPID".into(),
                     Variable {
-                        contents: ElviType::String(env!("CARGO_PKG_VERSION").to_string()),
+                        contents: ElviType::String(process::parent_id().to_string()),
                         ..Default::default()
                     },
                 ),
                 (
-                    "SHLVL".into(),
+                    "OPTIND".into(),
                     Variable {
-                        contents: ElviType::Number(1),
+                        contents: ElviType::String("1".into()),
                         ..Default::default()
                     },
                 ),
             ]),
 
             params: vec![],
+            exports: HashSet::new(),
+            frozen: HashMap::new(),
         }
     }
 }
@@ -334,7 +491,8 @@ impl Default for Variable {
         Self {
             contents: ElviType::String(String::new()),
             modification_status: ElviMutable::Normal,
-            shell_lvl: ElviGlobal::Global,
+            shell_lvl: ElviGlobal::Normal(1),
+            exported: false,
             line: (0, 0),
         }
     }
@@ -359,20 +517,6 @@ impl From<ElviType> for Variable {
 }
 
 impl ElviType {
-    /// Return an escaped string using [`backslash::escape_ascii`].
-    ///
-    /// # Panics
-    /// Can panic if the string cannot be converted a [`String`] to a UTF-8 byte vector.
-    #[must_use]
-    pub fn eval_escapes(&self) -> Self {
-        match self {
-            Self::VariableSubstitution(le_string) => {
-                Self::VariableSubstitution(backslash::escape_ascii(le_string).unwrap())
-            }
-            default => default.clone(),
-        }
-    }
-
     /// Convert a [`ElviType::ErrExitCode`] into a [`ReturnCode`].
     #[must_use]
     pub fn convert_err_type(&self) -> ReturnCode {
@@ -390,221 +534,646 @@ impl ElviType {
     /// 3. Converts `~bob/foo` -> `/home/bob/foo`
     /// 4. Converts `~bob` -> `/home/bob`
     ///
-    /// # Panics
-    /// This can panic if a username is found but a path cannot be found for it. You will probably
-    /// *never* encounter this, and if you do, you have bigger issues than a panic.
+    /// An unknown user, or `~` without a `$HOME`, is left as it is.
     ///
     /// # Notes
-    /// Requires [`ElviType::BareString`].
+    /// Requires [`ElviType::BareString`], or a [`ElviType::Word`] starting with one, where the
+    /// tilde prefix can't have anything quoted in it.
     #[must_use]
     pub fn tilde_expansion(&self, vars: &Variables) -> Self {
         match self {
-            Self::BareString(le_string) => {
-                let path = Path::new(le_string);
-                // So in POSIX, you can have two (*three) forms:
-                //
-                // ```bash
-                // ~/foo
-                // ~henry/oof
-                // ~
-                // ```
-
-                // So first let's check if there's even a tilde at the start to speed things up.
-                if !le_string.starts_with('~') {
-                    return Self::BareString(le_string.to_string());
+            Self::Word(parts) => match parts.split_first() {
+                Some((first @ Self::BareString(le_string), rest)) if le_string.contains('/') => {
+                    Self::Word(
+                        iter::once(first.tilde_expansion(vars))
+                            .chain(rest.iter().cloned())
+                            .collect(),
+                    )
                 }
+                _ => self.clone(),
+            },
+            Self::BareString(le_string) => Self::BareString(expand_tilde(le_string, vars)),
+            default => default.clone(),
+        }
+    }
 
-                if le_string == "~" {
-                    return Self::BareString(
-                        vars.get_variable("HOME").unwrap().contents.to_string(),
-                    );
-                // Do we have a tilde starting path?
-                } else if path.starts_with("~/") {
-                    let mut transform = path.display().to_string();
-                    transform.replace_range(
-                        0..1,
-                        &vars.get_variable("HOME").unwrap().contents.to_string(),
-                    );
-                    return Self::BareString(transform);
-                // At this point, after the previous checks, we can reasonably assume that we are
-                // left with a tilde user expansion.
-                } else if path.to_str().unwrap().starts_with('~') {
-                    let expanded_path: String = match path.parent() {
-                        // We have something like `~foo`.
-                        Some(p) if p == Path::new("") => {
-                            let without_tilde = &path.to_str().unwrap()[1..];
-                            let home = get_home(without_tilde).ok().flatten();
-                            handle_home(home, &[], &path.display().to_string())
-                        }
-                        // This means we have the tilde user + paths after
-                        Some(x) => {
-                            let without_tilde = &x.to_str().unwrap()[1..];
-                            let rest: Vec<&OsStr> = path.iter().skip(1).collect();
-                            let home = get_home(without_tilde).ok().flatten();
-                            handle_home(home, &rest, &path.display().to_string())
-                        }
-                        None => le_string.to_string(),
-                    };
-                    return Self::BareString(expanded_path);
-                }
-                Self::BareString(le_string.to_string())
+    /// Tilde substitution for the value of an assignment, where a tilde prefix can also come
+    /// after any unquoted `:`, like in `PATH=~/bin:~bob/bin:$PATH`.
+    ///
+    /// # Notes
+    /// Like [`ElviType::tilde_expansion`], only [`ElviType::BareString`] parts are looked at.
+    #[must_use]
+    pub fn assignment_tilde_expansion(&self, vars: &Variables) -> Self {
+        match self {
+            Self::BareString(le_string) => {
+                Self::BareString(expand_assignment_tildes(le_string, true, true, vars))
             }
+            Self::Word(parts) => Self::Word(
+                parts
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, part)| match part {
+                        Self::BareString(le_string) => Self::BareString(expand_assignment_tildes(
+                            le_string,
+                            idx == 0,
+                            idx == parts.len() - 1,
+                            vars,
+                        )),
+                        default => default.clone(),
+                    })
+                    .collect(),
+            ),
             default => default.clone(),
         }
     }
 
     /// This assumes [`ElviType::VariableSubstitution`]. If not, it will return the text given as
     /// is.
+    ///
+    /// # Notes
+    /// Command substitutions and arithmetic expansions are left as they are, and expansions that
+    /// fail only print why, use [`ElviType::eval_variables_with`] to handle them.
     #[must_use]
     pub fn eval_variables(&self, vars: &Variables) -> Self {
+        self.eval_variables_with(vars, &mut |substitution| {
+            if let Substitution::Failed(oops) = substitution {
+                eprintln!("{oops}");
+            }
+            None
+        })
+    }
+
+    /// Same as [`ElviType::eval_variables`], but command substitutions and arithmetic expansions
+    /// are replaced by whatever `substitute` returns when given them.
+    ///
+    /// If `substitute` returns [`None`], they are left as they are.
+    #[must_use]
+    pub fn eval_variables_with(
+        &self,
+        vars: &Variables,
+        substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+    ) -> Self {
         match self {
+            ElviType::CommandSubstitution(body) => {
+                match substitute(Substitution::Command(&unescape_backticks(body))) {
+                    Some(output) => Self::String(output),
+                    None => self.clone(),
+                }
+            }
             // So basically because of my shitty thinking, we set all double quotes to
             // [`ElviType::VariableSubstitution`] and convert that into a string. Haha.
             ElviType::VariableSubstitution(le_string) | ElviType::BareString(le_string) => {
                 // Let's skip the variables loops if we can't even find anything.
-                if !le_string.contains('$') && !le_string.contains(r"\$") {
+                if !le_string.contains(['$', '`', '\\']) {
                     return Self::String(le_string.to_string());
                 }
-                let mut chars_of = le_string.chars().peekable();
-                let mut back_to_string = String::new();
-                while let Some(charp) = chars_of.next() {
-                    // Do we have a variable that is escaped?
-                    if charp == '\\' && chars_of.peek() == Some(&'$') {
+                let mut fields = Fields::quoted();
+                self.expand_text(le_string, vars, substitute, &mut fields);
+                Self::String(fields.into_string())
+            }
+            ElviType::Word(parts) => Self::String(
+                parts
+                    .iter()
+                    .map(|part| part.eval_variables_with(vars, substitute).to_string())
+                    .collect(),
+            ),
+            default => default.clone(),
+        }
+    }
+
+    /// Expand a word into the fields it makes up, which are the results of its expansions split
+    /// on `$IFS`, unless it is quoted.
+    ///
+    /// Quoted words are expanded to a single field, apart from `"$@"`.
+    #[must_use]
+    pub fn expand_fields(
+        &self,
+        vars: &Variables,
+        substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+    ) -> Vec<String> {
+        match self {
+            Self::BareString(le_string) => {
+                let mut fields = Fields::split(vars);
+                self.expand_text(le_string, vars, substitute, &mut fields);
+                fields.finish_pathnames(vars)
+            }
+            Self::VariableSubstitution(le_string) => {
+                let mut fields = Fields::quoted();
+                self.expand_text(le_string, vars, substitute, &mut fields);
+                fields.finish()
+            }
+            // Only the unquoted parts are split, but they can still end up in the same field as the
+            // quoted ones, like `"$dir"/$file`.
+            Self::Word(parts) => {
+                let mut fields = Fields::split(vars);
+                for part in parts {
+                    match part {
+                        Self::BareString(le_string) => {
+                            part.expand_text(le_string, vars, substitute, &mut fields);
+                        }
+                        Self::VariableSubstitution(le_string) => {
+                            fields.set_quoted(true);
+                            part.expand_text(le_string, vars, substitute, &mut fields);
+                            fields.set_quoted(false);
+                        }
+                        literal => {
+                            fields.set_quoted(true);
+                            fields.push_literal(&literal.to_string());
+                            fields.set_quoted(false);
+                        }
+                    }
+                }
+                fields.finish_pathnames(vars)
+            }
+            quoted => vec![quoted.eval_variables_with(vars, substitute).to_string()],
+        }
+    }
+
+    /// Expand everything in the text of a word into `fields`, for
+    /// [`ElviType::eval_variables_with`] and [`ElviType::expand_fields`].
+    fn expand_text(
+        &self,
+        le_string: &str,
+        vars: &Variables,
+        substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+        fields: &mut Fields,
+    ) {
+        let mut chars_of = le_string.chars().peekable();
+        while let Some(charp) = chars_of.next() {
+            // Unquoted, a backslash quotes whatever comes after it, but inside of double quotes it
+            // only does before a few characters, according to
+            // <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_02>.
+            // Either way, a backslash and a newline are removed.
+            if charp == '\\' {
+                match chars_of.peek() {
+                    Some('\n') => {
                         chars_of.next();
-                        back_to_string.push('$');
-                    // Do we have a normal string please.
-                    } else if charp != '$' {
-                        back_to_string.push(charp);
-                    } else {
-                        // Ok at this point we have a variable! Woo, yay, congrats. Now is it a stupid
-                        // mfing $bare_variable or a lovely (we love) ${braced_variable}?
-                        // Oh and we're at '$' in the thing.
-                        if chars_of.peek() == Some(&'{') {
-                            // WOOOOOOOO
-                            chars_of.next();
-                            //BUG: What about ${foo\}bar}
-                            let tasty_var: String =
-                                chars_of.by_ref().take_while(|&c| c != '}').collect();
-                            // So here we have a list of expanded special vars, but assuming that
-                            // there are no special vars, we got len() == 0.
-                            let expanded_out = self.expand_param(&tasty_var, vars);
-                            for part in expanded_out {
-                                back_to_string.push_str(part.as_str());
-                            }
-                        } else {
-                            // Fuck.
-                            // Well before we fuck we should check if this is the last character, which
-                            // is stupid but hey, I'm writing a POSIX shell.
-                            if chars_of.peek().is_none() {
-                                back_to_string.push('$');
-                                continue;
-                            }
-                            // Ok now we fuck
-                            let tasty_var: String = chars_of
-                                .by_ref()
-                                // We don't wanna consume the character it fails on, otherwise we'd use
-                                // take_while() instead.
-                                .peeking_take_while(|&c| {
-                                    // TODO: Figure out how to work around `-` for it's special
-                                    // parameter.
-                                    // TODO: Also I need to figure out a better system than this.
-                                    c != ' ' && c != '\\' && c != ':' && c != '-'
-                                })
-                                .collect();
-                            let expanded_out = self.expand_param(&tasty_var, vars);
-                            for part in expanded_out {
-                                back_to_string.push_str(part.as_str());
-                            }
+                    }
+                    Some(&next) if !self.is_quoted() || matches!(next, '$' | '`' | '"' | '\\') => {
+                        chars_of.next();
+                        fields.push_char(next);
+                    }
+                    _ => fields.push_char(charp),
+                }
+            } else if charp == '`' {
+                let rest: Vec<char> = chars_of.clone().collect();
+                // A backtick that never closes is just a backtick.
+                let Some(end) = backtick_end(&rest) else {
+                    fields.push_char(charp);
+                    continue;
+                };
+                let body: String = rest[..end].iter().collect();
+                match substitute(Substitution::Command(&unescape_backticks(&body))) {
+                    Some(output) => fields.push_expanded(&output),
+                    None => fields.push_literal(&format!("`{body}`")),
+                }
+                chars_of.nth(end);
+            // Do we have a normal string please.
+            } else if charp != '$' {
+                if self.is_quoted() {
+                    fields.push_char(charp);
+                } else {
+                    fields.push_unquoted(charp);
+                }
+            } else {
+                // Ok at this point we have a variable! Woo, yay, congrats. Now is it a stupid
+                // mfing $bare_variable or a lovely (we love) ${braced_variable}?
+                // Oh and we're at '$' in the thing.
+                if chars_of.peek() == Some(&'(') {
+                    let rest: Vec<char> = chars_of.clone().skip(1).collect();
+                    // Same as backticks, a `$(` that never closes is just text.
+                    let Some(end) = substitution_end(&rest) else {
+                        fields.push_char(charp);
+                        continue;
+                    };
+                    let body: String = rest[..end].iter().collect();
+                    let output = match arithmetic_inner(&body) {
+                        // The expression gets expanded before it is evaluated.
+                        Some(expression) => {
+                            let expression = Self::VariableSubstitution(expression.into())
+                                .eval_variables_with(vars, substitute)
+                                .to_string();
+                            substitute(Substitution::Arithmetic(&expression))
                         }
+                        None => substitute(Substitution::Command(&body)),
+                    };
+                    match output {
+                        Some(output) => fields.push_expanded(&output),
+                        None => fields.push_literal(&format!("$({body})")),
                     }
+                    // Skip over the `(`, the body and the `)`.
+                    chars_of.nth(end + 1);
+                } else if chars_of.peek() == Some(&'{') {
+                    // WOOOOOOOO
+                    let rest: Vec<char> = chars_of.clone().skip(1).collect();
+                    // A `${` that never closes is just text too.
+                    let Some(end) = brace_end(&rest) else {
+                        fields.push_char(charp);
+                        continue;
+                    };
+                    let inside: String = rest[..end].iter().collect();
+                    // A plain `${foo}` is the same as `$foo`, just with a clear end.
+                    if !inside.is_empty() && param_name_len(&inside) == inside.len() {
+                        self.push_param(&inside, vars, substitute, fields);
+                    } else {
+                        fields.push_expanded(&self.expand_braced(&inside, vars, substitute));
+                    }
+                    // Skip over the `{`, the inside and the `}`.
+                    chars_of.nth(end + 1);
+                } else {
+                    // Fuck.
+                    // A bare name goes on for as long as it can, but `$12` is `${1}2`, and
+                    // special parameters are only ever one character.
+                    let tasty_var: String = match chars_of.peek() {
+                        Some(c) if c.is_ascii_alphabetic() || *c == '_' => chars_of
+                            .by_ref()
+                            // We don't wanna consume the character it fails on, otherwise we'd use
+                            // take_while() instead.
+                            .peeking_take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                            .collect(),
+                        Some(c) if c.is_ascii_digit() || "@*#?-$!".contains(*c) => {
+                            chars_of.next().unwrap().to_string()
+                        }
+                        // Well this is stupid, it was just a dollar sign, but hey, I'm writing
+                        // a POSIX shell.
+                        _ => {
+                            fields.push_char('$');
+                            continue;
+                        }
+                    };
+                    self.push_param(&tasty_var, vars, substitute, fields);
                 }
-                Self::String(back_to_string).eval_escapes()
             }
-            default => default.clone(),
         }
     }
 
-    /// This is for
-    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#2.5.2>
-    /// We also do variable expansion regardless in here.
+    /// Check if there is a command substitution, arithmetic expansion or an assignment like
+    /// `${foo:=bar}` anywhere in the text.
     #[must_use]
-    pub fn expand_param(&self, var: &str, variables: &Variables) -> Vec<String> {
-        let mut ret_vec = vec![];
-        match var {
-            // TODO: Fully flesh out the differences between these two.
-            "*" => {
-                if self.is_quoted() {
-                    let mut le_shit = vec![];
-                    for i in variables.params.iter().skip(1) {
-                        le_shit.push(i.contents.to_owned());
+    pub fn has_substitutions(&self) -> bool {
+        match self {
+            Self::CommandSubstitution(_) => true,
+            Self::Word(parts) => parts.iter().any(Self::has_substitutions),
+            Self::VariableSubstitution(le_string) | Self::BareString(le_string) => {
+                le_string.contains("$(")
+                    || le_string.contains('`')
+                    || le_string.match_indices("${").any(|(idx, _)| {
+                        let rest = &le_string[idx + 2..];
+                        let name_len = param_name_len(rest);
+                        name_len > 0
+                            && ["=", ":="]
+                                .iter()
+                                .any(|op| rest[name_len..].starts_with(op))
+                    })
+            }
+            _ => false,
+        }
+    }
+
+    /// Expand the inside of a `${...}`, according to
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_06_02>.
+    ///
+    /// ```bash
+    /// ${foo:-word} ${foo-word}  # `word` if `foo` is unset, or null with the colon.
+    /// ${foo:=word} ${foo=word}  # The same, but `foo` is assigned `word` too.
+    /// ${foo:?word} ${foo?word}  # Exit with `word` as the message instead.
+    /// ${foo:+word} ${foo+word}  # `word` if `foo` is set, and not null with the colon.
+    /// ${#foo}                   # How many characters are in `foo`.
+    /// ${foo%word} ${foo%%word}  # Remove the smallest/largest suffix matching `word`.
+    /// ${foo#word} ${foo##word}  # Remove the smallest/largest prefix matching `word`.
+    /// ```
+    ///
+    /// # Notes
+    /// `word` is only expanded when it is used. Expansions that fail are given to `substitute`,
+    /// and expand to nothing.
+    fn expand_braced(
+        &self,
+        inside: &str,
+        vars: &Variables,
+        substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+    ) -> String {
+        // `${#}` is the number of parameters, but `${#foo}` is the length of `foo`.
+        if let Some(name) = inside.strip_prefix('#') {
+            if !name.is_empty() && param_name_len(name) == name.len() {
+                let value = self.lookup_param(name, vars, substitute);
+                return match value {
+                    Some(value) => value.chars().count().to_string(),
+                    None if vars.has_flag('u') => {
+                        not_set(name, "parameter not set".into(), vars, substitute)
                     }
-                    return vec![split_ifs(&le_shit, variables)];
+                    None => "0".into(),
+                };
+            }
+        }
+        let (name, rest) = inside.split_at(param_name_len(inside));
+        let Some(op) = PARAM_OPERATORS
+            .iter()
+            .find(|op| !name.is_empty() && rest.starts_with(**op))
+        else {
+            return fail(
+                &VariableError::BadSubstitution {
+                    text: inside.to_string(),
+                },
+                substitute,
+            );
+        };
+        let word = &rest[op.len()..];
+        let value = self.lookup_param(name, vars, substitute);
+        // With the colon, being set to nothing is the same as not being set at all.
+        let is_set = value
+            .as_ref()
+            .is_some_and(|value| !(op.starts_with(':') && value.is_empty()));
+
+        match op.trim_start_matches(':') {
+            "-" if !is_set => self.expand_operand(word, vars, substitute),
+            "=" if !is_set => {
+                if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+                    return fail(
+                        &VariableError::CannotAssign { name: name.into() },
+                        substitute,
+                    );
                 }
+                let value = self.expand_operand(word, vars, substitute);
+                substitute(Substitution::Assignment {
+                    name,
+                    value: &value,
+                });
+                value
             }
-            "$" => {
-                ret_vec.push(process::id().to_string());
+            "?" if !is_set => {
+                let mut message = self.expand_operand(word, vars, substitute);
+                if message.is_empty() {
+                    message = if value.is_some() {
+                        "parameter null or not set".into()
+                    } else {
+                        "parameter not set".into()
+                    };
+                }
+                not_set(name, message, vars, substitute)
             }
-            "#" => ret_vec.push((variables.len_parameters().saturating_sub(1)).to_string()),
-            default => {
-                if let Some(woot) = variables.get_variable(default) {
-                    ret_vec.push(woot.contents.to_string());
+            "+" if is_set => self.expand_operand(word, vars, substitute),
+            "+" => String::new(),
+            "%" | "%%" | "#" | "##" => {
+                if value.is_none() && vars.has_flag('u') {
+                    return not_set(name, "parameter not set".into(), vars, substitute);
+                }
+                let mut pattern = Pattern::new();
+                for (text, quoted) in self.expand_operand_parts(word, vars, substitute) {
+                    if quoted {
+                        pattern.push_literal(&text);
+                    } else {
+                        pattern.push_pattern(&text);
+                    }
                 }
+                remove_matching(&value.unwrap_or_default(), op, &pattern)
             }
+            _ => value.unwrap_or_default(),
         }
-        ret_vec
     }
 
-    /// Expand globs using [`glob()`].
+    /// Expand a parameter into `fields`, where every positional parameter of `$@`, and of `$*`
+    /// unless it is quoted, is a field of its own.
+    fn push_param(
+        &self,
+        name: &str,
+        vars: &Variables,
+        substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+        fields: &mut Fields,
+    ) {
+        let values = match substitute(Substitution::Parameter(name)) {
+            Some(assigned) => vec![assigned],
+            None => self.expand_param(name, vars),
+        };
+        // With `-u`, expanding something that isn't set is an error, but `$@` and `$*` are always
+        // fine.
+        if values.is_empty() && !matches!(name, "@" | "*") && vars.has_flag('u') {
+            not_set(name, "parameter not set".into(), vars, substitute);
+            return;
+        }
+        // `"$@"` without any positional parameters is no field at all, not an empty one.
+        if name == "@" && values.is_empty() {
+            fields.discard_empty();
+        }
+        for (idx, value) in values.iter().enumerate() {
+            if idx > 0 {
+                fields.break_field();
+            }
+            fields.push_expanded(value);
+        }
+    }
+
+    /// The value of a parameter, or [`None`] if it isn't set.
+    fn lookup_param(
+        &self,
+        name: &str,
+        vars: &Variables,
+        substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+    ) -> Option<String> {
+        if let Some(assigned) = substitute(Substitution::Parameter(name)) {
+            return Some(assigned);
+        }
+        match name {
+            // These are only set when there are positional parameters.
+            "@" | "*" => (vars.len_parameters() > 1).then(|| {
+                vars.params
+                    .iter()
+                    .skip(1)
+                    .map(|param| param.contents.to_string())
+                    .join(" ")
+            }),
+            _ => {
+                let values = self.expand_param(name, vars);
+                (!values.is_empty()).then(|| values.concat())
+            }
+        }
+    }
+
+    /// Expand the word of a `${foo<op>word}` to a string, see
+    /// [`ElviType::expand_operand_parts`].
+    fn expand_operand(
+        &self,
+        word: &str,
+        vars: &Variables,
+        substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+    ) -> String {
+        self.expand_operand_parts(word, vars, substitute)
+            .into_iter()
+            .map(|(text, _)| text)
+            .collect()
+    }
+
+    /// Expand the word of a `${foo<op>word}`, which can be quoted and have expansions of its own:
     ///
-    /// # Notes
-    /// This function will return a list of paths it matches against, but if it does not match
-    /// anything, it will return the literal string that it received.
+    /// ```bash
+    /// echo ${name:-"$USER's $(hostname)"}
+    /// ```
     ///
-    /// # Panics
-    /// 1. This can panic if a string returned by [`glob()`] is not valid unicode, which in
-    ///    practice will never happen.
-    #[must_use = "Why no using the globs ya goof"]
-    pub fn expand_globs(&self) -> Vec<Self> {
-        let mut ret_vec = vec![];
-        match glob(&self.to_string()) {
-            Ok(paths) => {
-                for patho in paths {
-                    match patho {
-                        Ok(yay) => {
-                            ret_vec.push(ElviType::String(yay.to_str().unwrap().to_string()));
-                        }
-                        Err(boo) => eprintln!("{boo}"),
-                    }
-                }
-                // So because of the [`glob`] library, any path that doesn't match a filesystem
-                // path will be silently skipped, so what we're doing here is checking if that
-                // event happened, and pushing the literal string back, to conform to:
-                // ```shell
-                // for i in /foobar/*; do
-                //   echo "${i}"
-                // done
-                // # Prints: /foobar/*
-                // ```
-                if ret_vec.is_empty() {
-                    ret_vec.push(ElviType::String(self.to_string()));
+    /// This is also how the unquoted parts of a case pattern are expanded.
+    ///
+    /// # Returns
+    /// Every part of the word after it is expanded, and whether that part was quoted.
+    pub fn expand_operand_parts(
+        &self,
+        word: &str,
+        vars: &Variables,
+        substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+    ) -> Vec<(String, bool)> {
+        let chars: Vec<char> = word.chars().collect();
+        let mut parts = vec![];
+        let mut pending = String::new();
+        let mut idx = 0;
+        while let Some(&charp) = chars.get(idx) {
+            let rest = &chars[idx + 1..];
+            // Where whatever starts here ends, if it has to be kept whole.
+            let end = match charp {
+                '\\' if !rest.is_empty() => Some(1),
+                // Inside double quotes, single quotes are just single quotes.
+                '\'' if !self.is_quoted() => rest.iter().position(|c| *c == '\'').map(|e| e + 1),
+                '"' => double_quote_end(rest).map(|e| e + 1),
+                '`' => backtick_end(rest).map(|e| e + 1),
+                '$' if rest.first() == Some(&'(') => substitution_end(&rest[1..]).map(|e| e + 2),
+                '$' if rest.first() == Some(&'{') => brace_end(&rest[1..]).map(|e| e + 2),
+                _ => None,
+            };
+            let Some(end) = end else {
+                pending.push(charp);
+                idx += 1;
+                continue;
+            };
+            let text: String = chars[idx..=idx + end].iter().collect();
+            idx += end + 1;
+            // Expansions get expanded along with the rest of the unquoted text around them.
+            if matches!(charp, '`' | '$') {
+                pending.push_str(&text);
+                continue;
+            }
+            if !pending.is_empty() {
+                let unquoted = self.same_kind(mem::take(&mut pending));
+                parts.push((
+                    unquoted.eval_variables_with(vars, substitute).to_string(),
+                    false,
+                ));
+            }
+            let quoted = match charp {
+                '\\' => text[1..].to_string(),
+                '\'' => text[1..text.len() - 1].to_string(),
+                _ => Self::VariableSubstitution(text[1..text.len() - 1].to_string())
+                    .eval_variables_with(vars, substitute)
+                    .to_string(),
+            };
+            parts.push((quoted, true));
+        }
+        if !pending.is_empty() {
+            parts.push((
+                self.same_kind(pending)
+                    .eval_variables_with(vars, substitute)
+                    .to_string(),
+                false,
+            ));
+        }
+        parts
+    }
+
+    /// Create a [`ElviType::VariableSubstitution`] if this is quoted, otherwise a
+    /// [`ElviType::BareString`].
+    fn same_kind(&self, text: String) -> Self {
+        if self.is_quoted() {
+            Self::VariableSubstitution(text)
+        } else {
+            Self::BareString(text)
+        }
+    }
+
+    /// Expand a parameter, according to
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_05_02>.
+    ///
+    /// # Returns
+    /// Every field the parameter expands to, which is nothing if it isn't set. Only `$@` and `$*`
+    /// can expand to more than one, with a field for every positional parameter, except for
+    /// `"$*"` which joins them with the first character of `$IFS`.
+    #[must_use]
+    pub fn expand_param(&self, var: &str, variables: &Variables) -> Vec<String> {
+        match var {
+            "@" | "*" => {
+                let params = variables
+                    .params
+                    .iter()
+                    .skip(1)
+                    .map(|param| param.contents.to_string());
+                if var == "*" && self.is_quoted() {
+                    vec![join_ifs(params, variables)]
+                } else {
+                    params.collect()
                 }
             }
-            Err(oof) => eprintln!("{oof}"),
+            "#" => vec![(variables.len_parameters().saturating_sub(1)).to_string()],
+            default => variables
+                .get_variable(default)
+                .map(|woot| woot.contents.to_string())
+                .into_iter()
+                .collect(),
         }
-        ret_vec
     }
 }
 
-fn handle_home(home: Option<PathBuf>, rest: &[&OsStr], default: &str) -> String {
+/// Expand the tilde prefix at the start of some text, if it has one, according to
+/// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_06_01>.
+fn expand_tilde(text: &str, vars: &Variables) -> String {
+    let Some(after) = text.strip_prefix('~') else {
+        return text.to_string();
+    };
+    let (user, rest) = after.split_at(after.find('/').unwrap_or(after.len()));
+    let home = if user.is_empty() {
+        vars.get_variable("HOME")
+            .map(|home| home.contents.to_string())
+    } else {
+        get_home(user)
+            .ok()
+            .flatten()
+            .map(|dir| dir.display().to_string())
+    };
     match home {
-        Some(mut dir) => {
-            for part in rest {
-                dir.push(part);
+        Some(home) => format!("{home}{rest}"),
+        None => text.to_string(),
+    }
+}
+
+/// Expand the tilde prefixes after every unescaped `:` in part of an assignment, as well as the
+/// one at the start if the part is at the start of the value.
+///
+/// If the part is followed by a quoted one, the tilde prefix in its last segment has to end in
+/// a `/`, otherwise it would have something quoted in it.
+fn expand_assignment_tildes(
+    text: &str,
+    at_start: bool,
+    complete: bool,
+    vars: &Variables,
+) -> String {
+    let mut segments = vec![String::new()];
+    let mut chars = text.chars();
+    while let Some(charp) = chars.next() {
+        match charp {
+            ':' => segments.push(String::new()),
+            '\\' => {
+                let last = segments.last_mut().unwrap();
+                last.push(charp);
+                last.extend(chars.next());
             }
-            dir.display().to_string()
+            _ => segments.last_mut().unwrap().push(charp),
         }
-        None => default.to_string(),
     }
+    let last = segments.len() - 1;
+    segments
+        .iter()
+        .enumerate()
+        .map(|(idx, segment)| {
+            if (idx == 0 && !at_start) || (idx == last && !complete && !segment.contains('/')) {
+                segment.clone()
+            } else {
+                expand_tilde(segment, vars)
+            }
+        })
+        .join(":")
 }
 
 impl fmt::Display for ElviType {
@@ -617,36 +1186,598 @@ impl fmt::Display for ElviType {
             ElviType::Number(x) => write!(f, "{x}"),
             ElviType::ErrExitCode(x) => write!(f, "{x}"),
             ElviType::Boolean(x) => write!(f, "{x}"),
+            ElviType::Word(parts) => parts.iter().try_for_each(|part| write!(f, "{part}")),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy)]
+/// Something in a word that has to be run to find out what it expands to.
+pub enum Substitution<'a> {
+    /// The body of a `$(...)` or backticks.
+    Command(&'a str),
+    /// The expression of a `$((...))`, already expanded.
+    Arithmetic(&'a str),
+    /// A `${name:=value}` that assigns `name`, with `value` already expanded.
+    Assignment { name: &'a str, value: &'a str },
+    /// A parameter that is about to be expanded, which should be replaced with what it was
+    /// assigned earlier on in the same word, if it was.
+    Parameter(&'a str),
+    /// An expansion that failed, like `${name?}` when `name` isn't set.
+    Failed(&'a VariableError),
+}
+
 #[derive(Debug, Clone)]
 /// Struct for global arguments
 pub struct Arguments {
     pub args: Vec<String>,
+    /// Extensions turned on from the command line.
+    pub extensions: Vec<Extension>,
+    /// Option letters the shell was started with, which `$-` expands to.
+    pub flags: String,
+    /// The only variables imported from the environment, or [`None`] to import all of them.
+    pub environment: Option<Vec<String>>,
 }
 
 impl From<Vec<String>> for Arguments {
     fn from(value: Vec<String>) -> Self {
-        Self { args: value }
+        Self {
+            args: value,
+            extensions: vec![],
+            flags: String::new(),
+            environment: None,
+        }
     }
 }
 
-/// Split string according to IFS.
-pub fn split_ifs(to_split: &[ElviType], vars: &Variables) -> String {
-    let mut reto = vec![];
-    let ifs = match vars.get_variable("IFS") {
-        Some(yay) => match yay.contents.to_string().chars().next() {
-            Some(y) => y.to_string(),
-            None => String::new(),
-        },
-        None => " ".to_string(),
+/// Join the positional parameters for `"$*"`, with the first character of `$IFS` between them.
+///
+/// When `$IFS` is unset that's a space, and when it is empty they are joined with nothing.
+pub fn join_ifs(mut params: impl Iterator<Item = String>, vars: &Variables) -> String {
+    let separator = match vars.get_variable("IFS") {
+        Some(ifs) => ifs.contents.to_string().chars().next(),
+        None => Some(' '),
     };
-    for part in to_split {
-        if !part.is_quoted() {
-            reto.push(part);
+    params.join(&separator.map(String::from).unwrap_or_default())
+}
+
+/// Find where a `$(...)` ends, given everything right after its `(`.
+///
+/// # Returns
+/// The index of the `)` that closes it, or [`None`] if it never closes.
+pub fn substitution_end(chars: &[char]) -> Option<usize> {
+    let mut idx = 0;
+    while let Some(charp) = chars.get(idx) {
+        match charp {
+            ')' => return Some(idx),
+            '\\' => idx += 1,
+            '\'' => idx += chars[idx + 1..].iter().position(|c| *c == '\'')? + 1,
+            '"' => idx += double_quote_end(&chars[idx + 1..])? + 1,
+            '`' => idx += backtick_end(&chars[idx + 1..])? + 1,
+            '(' => idx += substitution_end(&chars[idx + 1..])? + 1,
+            _ => {}
         }
+        idx += 1;
+    }
+    None
+}
+
+/// Get the expression out of the body of a `$((...))`, or [`None`] if it is a command
+/// substitution that just starts with a subshell, like `$((cd foo); ls)`.
+fn arithmetic_inner(body: &str) -> Option<&str> {
+    let inner = body.strip_prefix('(')?.strip_suffix(')')?;
+    let chars: Vec<char> = body.chars().skip(1).collect();
+    (substitution_end(&chars)? == chars.len() - 1).then_some(inner)
+}
+
+/// Operators that can follow the name in a `${...}`, longest first so `:-` isn't taken for `:`.
+const PARAM_OPERATORS: [&str; 12] = [
+    ":-", ":=", ":?", ":+", "%%", "##", "-", "=", "?", "+", "%", "#",
+];
+
+/// Get how long the parameter name at the start of `text` is, which is either a name, a number, or
+/// a single special parameter.
+fn param_name_len(text: &str) -> usize {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            1 + chars
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                .count()
+        }
+        Some(c) if c.is_ascii_digit() => 1 + chars.take_while(char::is_ascii_digit).count(),
+        Some('@' | '*' | '#' | '?' | '-' | '$' | '!') => 1,
+        _ => 0,
+    }
+}
+
+/// Remove the part of `value` that matches `pattern`, for `${foo%word}` and friends.
+///
+/// `#` and `##` remove the smallest and largest prefix, `%` and `%%` the smallest and largest
+/// suffix.
+fn remove_matching(value: &str, op: &str, pattern: &Pattern) -> String {
+    let mut splits: Vec<usize> = value
+        .char_indices()
+        .map(|(idx, _)| idx)
+        .chain(iter::once(value.len()))
+        .collect();
+    // Going forwards, prefixes get bigger and suffixes get smaller.
+    if matches!(op, "##" | "%") {
+        splits.reverse();
+    }
+    for split in splits {
+        let (prefix, suffix) = value.split_at(split);
+        if op.starts_with('#') && pattern.matches(prefix) {
+            return suffix.to_string();
+        } else if op.starts_with('%') && pattern.matches(suffix) {
+            return prefix.to_string();
+        }
+    }
+    value.to_string()
+}
+
+/// Give `substitute` why an expansion failed, which a non-interactive shell has to exit for.
+///
+/// # Returns
+/// An empty string, which is what the expansion is replaced with.
+fn fail(
+    oops: &VariableError,
+    substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+) -> String {
+    substitute(Substitution::Failed(oops));
+    String::new()
+}
+
+/// Fail because `name` isn't set, like `${name?message}` does, on whatever line is running.
+fn not_set(
+    name: &str,
+    message: String,
+    vars: &Variables,
+    substitute: &mut dyn FnMut(Substitution) -> Option<String>,
+) -> String {
+    fail(
+        &VariableError::NotSet {
+            name: name.into(),
+            message,
+            line: vars
+                .get_variable("LINENO")
+                .and_then(|line| line.contents.to_string().parse().ok())
+                .unwrap_or_default(),
+        },
+        substitute,
+    )
+}
+
+/// Find where a `${...}` ends, given everything right after its `{`.
+///
+/// # Returns
+/// The index of the `}` that closes it, or [`None`] if it never closes.
+pub fn brace_end(chars: &[char]) -> Option<usize> {
+    let mut idx = 0;
+    while let Some(charp) = chars.get(idx) {
+        match charp {
+            '}' => return Some(idx),
+            '\\' => idx += 1,
+            '\'' => idx += chars[idx + 1..].iter().position(|c| *c == '\'')? + 1,
+            '"' => idx += double_quote_end(&chars[idx + 1..])? + 1,
+            '`' => idx += backtick_end(&chars[idx + 1..])? + 1,
+            '$' if chars.get(idx + 1) == Some(&'(') => {
+                idx += substitution_end(&chars[idx + 2..])? + 2;
+            }
+            '$' if chars.get(idx + 1) == Some(&'{') => {
+                idx += brace_end(&chars[idx + 2..])? + 2;
+            }
+            _ => {}
+        }
+        idx += 1;
+    }
+    None
+}
+
+/// Find where a double quoted string ends, given everything right after its `"`.
+fn double_quote_end(chars: &[char]) -> Option<usize> {
+    let mut idx = 0;
+    while let Some(charp) = chars.get(idx) {
+        match charp {
+            '"' => return Some(idx),
+            '\\' => idx += 1,
+            '`' => idx += backtick_end(&chars[idx + 1..])? + 1,
+            '$' if chars.get(idx + 1) == Some(&'(') => {
+                idx += substitution_end(&chars[idx + 2..])? + 2;
+            }
+            '$' if chars.get(idx + 1) == Some(&'{') => {
+                idx += brace_end(&chars[idx + 2..])? + 2;
+            }
+            _ => {}
+        }
+        idx += 1;
+    }
+    None
+}
+
+/// Find where a backtick substitution ends, given everything right after its `` ` ``.
+pub fn backtick_end(chars: &[char]) -> Option<usize> {
+    let mut idx = 0;
+    while let Some(charp) = chars.get(idx) {
+        match charp {
+            '`' => return Some(idx),
+            '\\' => idx += 1,
+            _ => {}
+        }
+        idx += 1;
+    }
+    None
+}
+
+/// Inside of backticks, a backslash only escapes `$`, `` ` `` and `\\`.
+fn unescape_backticks(body: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = body.chars().peekable();
+    while let Some(charp) = chars.next() {
+        if charp == '\\' && matches!(chars.peek(), Some('$' | '`' | '\\')) {
+            unescaped.push(chars.next().unwrap());
+        } else {
+            unescaped.push(charp);
+        }
+    }
+    unescaped
+}
+
+/// Variables the shell always sets up itself, instead of taking them from the environment.
+const NOT_IMPORTED: &[&str] = &["IFS", "ELVI_VERSION", "SHLVL", "PPID", "OPTIND"];
+
+/// The `$PATH` used when there isn't one in the environment.
+const DEFAULT_PATH: &str = "/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Whether a path is an absolute path to the current directory, so it can be used as `$PWD`.
+fn is_current_dir(path: &str) -> bool {
+    Path::new(path).is_absolute()
+        && fs::canonicalize(path).ok() == env::current_dir().and_then(fs::canonicalize).ok()
+}
+
+/// Whether some text can be the name of a variable, according to
+/// <https://pubs.opengroup.org/onlinepubs/9699919799/basedefs/V1_chap03.html#tag_03_235>.
+#[must_use]
+pub fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && text.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Quote text so that it can be read back in as the same word, like `'it'\''s'`.
+#[must_use]
+pub fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Which backslash escapes [`unescape`] replaces, since `$'...'` and `echo` don't agree.
+pub enum Escapes {
+    /// A `$'...'` string, like in C.
+    ///
+    /// `\a`, `\b`, `\e`, `\f`, `\n`, `\r`, `\t`, `\v`, `\\`, `\'`, `\"` and `\?` are what they are
+    /// in C, `\nnn` is an octal byte, `\xHH` is a hexadecimal byte and `\cX` is control-X.
+    AnsiC,
+    /// The arguments of `echo`, according to the XSI extension of
+    /// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/echo.html>.
+    ///
+    /// `\a`, `\b`, `\f`, `\n`, `\r`, `\t`, `\v` and `\\` are the same as in C, but `\0nnn` is an
+    /// octal byte and `\c` cuts the output short.
+    Echo,
+}
+
+/// Replace the backslash escapes in text. Anything that isn't one is left as it is.
+///
+/// # Returns
+/// The bytes the text stands for, since an escape can be any byte, and whether it was cut short
+/// by `\c`, which only happens with [`Escapes::Echo`].
+#[must_use]
+pub fn unescape(text: &str, escapes: Escapes) -> (Vec<u8>, bool) {
+    let ansi_c = escapes == Escapes::AnsiC;
+    let mut bytes = vec![];
+    let mut chars = text.chars().peekable();
+    while let Some(charp) = chars.next() {
+        if charp != '\\' {
+            bytes.extend_from_slice(charp.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        let byte = match chars.next() {
+            Some('a') => 0x07,
+            Some('b') => 0x08,
+            Some('e' | 'E') if ansi_c => 0x1b,
+            Some('f') => 0x0c,
+            Some('n') => b'\n',
+            Some('r') => b'\r',
+            Some('t') => b'\t',
+            Some('v') => 0x0b,
+            Some('\\') => b'\\',
+            Some(escaped @ ('\'' | '"' | '?')) if ansi_c => escaped as u8,
+            // `$'\nnn'` has up to three octal digits, while `echo` has up to three after a `\0`.
+            Some(first @ '0'..='7') if ansi_c || first == '0' => {
+                let (mut value, digits) = if ansi_c {
+                    (first.to_digit(8).unwrap(), 2)
+                } else {
+                    (0, 3)
+                };
+                for _ in 0..digits {
+                    let Some(digit) = chars.peek().and_then(|c| c.to_digit(8)) else {
+                        break;
+                    };
+                    value = value * 8 + digit;
+                    chars.next();
+                }
+                // Anything past a byte wraps around.
+                value.to_le_bytes()[0]
+            }
+            Some('x') if ansi_c && chars.peek().is_some_and(char::is_ascii_hexdigit) => {
+                let mut value = 0;
+                for _ in 0..2 {
+                    let Some(digit) = chars.peek().and_then(|c| c.to_digit(16)) else {
+                        break;
+                    };
+                    value = value * 16 + digit;
+                    chars.next();
+                }
+                value.to_le_bytes()[0]
+            }
+            Some('c') if !ansi_c => return (bytes, true),
+            Some('c') if chars.peek().is_some_and(char::is_ascii) => {
+                chars.next().unwrap().to_ascii_uppercase() as u8 ^ 0x40
+            }
+            Some(other) => {
+                bytes.push(b'\\');
+                bytes.extend_from_slice(other.encode_utf8(&mut [0; 4]).as_bytes());
+                continue;
+            }
+            None => b'\\',
+        };
+        bytes.push(byte);
+    }
+    (bytes, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_params(params: &[&str]) -> Variables {
+        let mut vars = Variables::default();
+        let list: Vec<Variable> = iter::once("elvi")
+            .chain(params.iter().copied())
+            .map(|param| param.to_string().into())
+            .collect();
+        vars.new_parameters(&list);
+        vars
+    }
+
+    fn fields(word: &ElviType, vars: &Variables) -> Vec<String> {
+        word.expand_fields(vars, &mut |_| None)
+    }
+
+    fn quoted(text: &str) -> ElviType {
+        ElviType::VariableSubstitution(text.into())
+    }
+
+    fn bare(text: &str) -> ElviType {
+        ElviType::BareString(text.into())
+    }
+
+    #[test]
+    fn quoted_at_is_a_field_per_parameter() {
+        let vars = with_params(&["a b", "", "c"]);
+        assert_eq!(vec!["a b", "", "c"], fields(&quoted("$@"), &vars));
+        assert_eq!(vec!["a b", "", "c"], fields(&quoted("${@}"), &vars));
+        assert_eq!(vec!["xa b", "", "cy"], fields(&quoted("x$@y"), &vars));
+        assert!(fields(&quoted("$@"), &with_params(&[])).is_empty());
+        assert_eq!(vec!["xy"], fields(&quoted("x$@y"), &with_params(&[])));
+    }
+
+    #[test]
+    fn quoted_star_joins_with_ifs() {
+        let mut vars = with_params(&["a b", "", "c"]);
+        assert_eq!(vec!["a b  c"], fields(&quoted("$*"), &vars));
+        vars.set_variable("IFS", ElviType::String(",:".into()))
+            .unwrap();
+        assert_eq!(vec!["a b,,c"], fields(&quoted("$*"), &vars));
+        vars.set_variable("IFS", ElviType::String(String::new()))
+            .unwrap();
+        assert_eq!(vec!["a bc"], fields(&quoted("${*}"), &vars));
+        vars.unset("IFS");
+        assert_eq!(vec!["a b  c"], fields(&quoted("$*"), &vars));
+    }
+
+    #[test]
+    fn unquoted_at_and_star_are_split() {
+        let vars = with_params(&["a b", "", "c"]);
+        assert_eq!(vec!["a", "b", "c"], fields(&bare("$@"), &vars));
+        assert_eq!(vec!["a", "b", "c"], fields(&bare("$*"), &vars));
+        assert!(fields(&bare("$@"), &with_params(&[])).is_empty());
+    }
+
+    #[test]
+    fn positional_parameters_are_one_digit() {
+        let params: Vec<String> = (1..=10).map(|num| format!("p{num}")).collect();
+        let params: Vec<&str> = params.iter().map(String::as_str).collect();
+        let vars = with_params(&params);
+        assert_eq!(vec!["p12"], fields(&bare("$12"), &vars));
+        assert_eq!(vec!["p10"], fields(&bare("${10}"), &vars));
+        assert_eq!(vec!["10"], fields(&bare("$#"), &vars));
+        assert_eq!(vec!["10"], fields(&bare("${#}"), &vars));
+        assert_eq!(vec!["elvi"], fields(&bare("$0"), &vars));
+    }
+
+    #[test]
+    fn special_parameters() {
+        let mut vars = with_params(&[]);
+        assert!(fields(&bare("$!"), &vars).is_empty());
+        assert_eq!(vec![""], fields(&quoted("$-"), &vars));
+        vars.set_special("!", ElviType::String("1234".into()));
+        vars.set_special("-", ElviType::String("c".into()));
+        vars.set_special("$", ElviType::String("42".into()));
+        assert_eq!(vec!["1234-c-42"], fields(&bare("$!-$--$$"), &vars));
+        assert_eq!(vec!["0"], fields(&bare("$?"), &vars));
+        assert_eq!(vec!["a$"], fields(&bare("a$"), &vars));
+    }
+
+    #[test]
+    fn word_parts_keep_their_quoting() {
+        let mut vars = with_params(&["p", "q"]);
+        vars.set_variable("dir", ElviType::String("a b".into()))
+            .unwrap();
+        let word = ElviType::Word(vec![quoted("$dir"), bare("/$dir")]);
+        assert_eq!(vec!["a b/a", "b"], fields(&word, &vars));
+        let word = ElviType::Word(vec![
+            bare("x"),
+            ElviType::String("$dir".into()),
+            quoted("$@"),
+        ]);
+        assert_eq!(vec!["x$dirp", "q"], fields(&word, &vars));
+        let word = ElviType::Word(vec![quoted(""), bare("$nothing")]);
+        assert_eq!(vec![""], fields(&word, &vars));
+        assert_eq!(
+            "x$dira b",
+            ElviType::Word(vec![
+                bare("x"),
+                ElviType::String("$dir".into()),
+                quoted("$dir")
+            ])
+            .eval_variables(&vars)
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn only_exported_variables_reach_the_environment() {
+        let mut vars = Variables::default();
+        vars.fill_defaults();
+        vars.set_variable("foo", ElviType::String("bar".into()))
+            .unwrap();
+        assert!(!vars.get_environmentals().contains_key("foo"));
+        assert!(!vars.get_environmentals().contains_key("?"));
+        assert!(vars.get_environmentals().contains_key("PWD"));
+
+        // It stays exported when it's changed.
+        vars.export("foo");
+        vars.set_variable("foo", ElviType::String("baz".into()))
+            .unwrap();
+        assert_eq!(
+            Some("baz"),
+            vars.get_environmentals().get("foo").map(String::as_str)
+        );
+
+        // Exporting a name before it's set exports it once it is, unless it's unset first.
+        vars.export("later");
+        vars.export("never");
+        vars.unset("never");
+        vars.set_variable("later", ElviType::String("1".into()))
+            .unwrap();
+        vars.set_variable("never", ElviType::String("1".into()))
+            .unwrap();
+        assert!(vars.get_environmentals().contains_key("later"));
+        assert!(!vars.get_environmentals().contains_key("never"));
+    }
+
+    #[test]
+    fn environment_can_be_imported_selectively() {
+        let mut vars = Variables::default();
+        vars.import_environment(Some(&[]));
+        vars.fill_defaults();
+        assert_eq!(
+            vec!["PWD"],
+            vars.get_environmentals().into_keys().collect::<Vec<_>>()
+        );
+
+        let mut vars = Variables::default();
+        vars.import_environment(Some(&["PATH".into(), "IFS".into()]));
+        // `PATH` is imported as it is, or not at all when the tests are run without it.
+        let path = env::var("PATH").ok();
+        assert_eq!(path.as_ref(), vars.get_environmentals().get("PATH"));
+        assert_eq!(
+            " \t\n",
+            vars.get_variable("IFS").unwrap().contents.to_string()
+        );
+    }
+
+    #[test]
+    fn defaults_only_fill_what_is_missing() {
+        let mut vars = Variables::default();
+        assert!(vars.get_variable("PATH").is_none());
+        vars.set_variable("PATH", ElviType::String("/opt/bin".into()))
+            .unwrap();
+        vars.fill_defaults();
+        assert_eq!(
+            "/opt/bin",
+            vars.get_variable("PATH").unwrap().contents.to_string()
+        );
+        assert!(vars.get_environmentals().contains_key("PWD"));
+        assert!(!vars.get_environmentals().contains_key("OLDPWD"));
+        assert!(vars.get_variable("HOME").is_none());
+        assert_eq!(
+            "1",
+            vars.get_variable("OPTIND").unwrap().contents.to_string()
+        );
+    }
+
+    #[test]
+    fn assignments_expand_tildes_after_colons() {
+        let mut vars = Variables::default();
+        vars.set_variable("HOME", ElviType::String("/home/me".into()))
+            .unwrap();
+        let expand =
+            |word: ElviType, vars: &Variables| word.assignment_tilde_expansion(vars).to_string();
+        assert_eq!(
+            "/home/me/bin:/home/me:x~:~nosuchuser/x",
+            expand(bare("~/bin:~:x~:~nosuchuser/x"), &vars)
+        );
+        assert_eq!("a\\:~/b", expand(bare("a\\:~/b"), &vars));
+        assert_eq!(
+            "~/x:/home/me/y",
+            expand(ElviType::Word(vec![quoted("~"), bare("/x:~/y")]), &vars)
+        );
+        assert_eq!(
+            "/home/me/:~/z",
+            expand(ElviType::Word(vec![bare("~/:~"), quoted("/z")]), &vars)
+        );
+        // Outside of assignments, only the start counts.
+        assert_eq!(
+            "/home/me/:~",
+            bare("~/:~").tilde_expansion(&vars).to_string()
+        );
+        vars.unset("HOME");
+        assert_eq!("~/bin", expand(bare("~/bin"), &vars));
+    }
+
+    #[test]
+    fn backslashes_depend_on_quoting() {
+        let vars = Variables::default();
+        let expand = |word: ElviType| word.eval_variables(&vars).to_string();
+        assert_eq!(r#"a\nb"$x`\"#, expand(quoted(r#"a\nb\"\$x\`\\"#)));
+        assert_eq!("anb$x ", expand(bare(r"a\nb\$x\ ")));
+        assert_eq!("ab", expand(quoted("a\\\nb")));
+        assert_eq!("ab", expand(bare("a\\\nb")));
+    }
+
+    #[test]
+    fn ansi_c_strings_are_unescaped() {
+        let unescape_ansi_c = |text| unescape(text, Escapes::AnsiC);
+        assert_eq!((b"a\tb\n".to_vec(), false), unescape_ansi_c(r"a\tb\n"));
+        assert_eq!(
+            (b"\x1b[0m'\"".to_vec(), false),
+            unescape_ansi_c(r#"\e[0m\'\""#)
+        );
+        assert_eq!((b"AA\x01".to_vec(), false), unescape_ansi_c(r"\101\x41\ca"));
+        assert_eq!((br"\q\x".to_vec(), false), unescape_ansi_c(r"\q\x"));
+        assert_eq!((vec![0xff], false), unescape_ansi_c(r"\xff"));
+    }
+
+    #[test]
+    fn echo_escapes_are_replaced() {
+        let unescape_echo = |text| unescape(text, Escapes::Echo);
+        assert_eq!((b"a\tb\n\\q".to_vec(), false), unescape_echo(r"a\tb\n\q"));
+        assert_eq!(
+            (b"\x1b[0mA".to_vec(), false),
+            unescape_echo(r"\033[0m\0101")
+        );
+        assert_eq!((b"stop".to_vec(), true), unescape_echo(r"stop\cnot"));
+        assert_eq!((vec![0xff], false), unescape_echo(r"\0377"));
     }
-    reto.iter().join(&ifs)
 }
@@ -48,26 +48,34 @@ fn main() {
         }
     };
 
-    let file = if let Some(ref v) = args.group.file {
-        v.to_str().unwrap()
-    } else {
-        "stdin"
+    let file = match (&args.group.read_from_input, &args.group.file) {
+        (None, Some(v)) => v.to_str().unwrap(),
+        _ => "stdin",
     };
 
-    let var_zero = if args.group.read_from_input.is_some() {
-        env::current_exe().unwrap().to_str().unwrap().to_string()
-    } else {
-        args.group
+    // With `-c`, the operand after the command string is `$0` instead of a file, and otherwise
+    // it's the shell itself.
+    let var_zero = match (&args.group.read_from_input, &args.group.file) {
+        (Some(_), Some(command_name)) => command_name.display().to_string(),
+        (Some(_), None) => env::args().next().unwrap_or_else(|| "elvi".into()),
+        (None, _) => args
+            .group
             .file
             .as_ref()
             .unwrap()
             .to_str()
             .unwrap()
-            .to_string()
+            .to_string(),
     };
 
     let mut positionals: Arguments = vec![var_zero].into();
-
+    positionals.extensions.clone_from(&args.extensions);
+    if args.clean_env || !args.keep_env.is_empty() {
+        positionals.environment = Some(args.keep_env.clone());
+    }
+    if args.group.read_from_input.is_some() {
+        positionals.flags.push('c');
+    }
     if let Some(mut positional_args) = args.positionals {
         positionals.args.append(&mut positional_args);
     }
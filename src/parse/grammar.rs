@@ -1,13 +1,26 @@
 use crate::internal::builtins;
 use crate::internal::commands::{execute_external_command, Commands, ExternalCommand};
-use crate::internal::env::Env;
+use crate::internal::env::{ControlFlow, Env};
 use crate::internal::errors::ElviError;
+use crate::internal::pattern::Pattern;
+use crate::internal::pipeline::{execute_background, execute_pipeline};
+use crate::internal::redirection::{apply_redirections, restore_redirections};
 use crate::internal::status::ReturnCode;
+use crate::internal::substitution::{
+    expand_redirection, expand_substitutions, expand_word, substitute, SideEffects,
+};
 use crate::internal::tree::Function;
-use crate::internal::tree::{change_variable, Actions, Builtins, Conditional, Loop, TestOptions};
+use crate::internal::tree::{
+    change_variable, Actions, Builtins, Case, CaseItem, Conditional, ConditionalLoop, Loop,
+    Redirection, RedirectionKind, TestOptions,
+};
 use crate::internal::variables::Arguments;
-use crate::internal::variables::{ElviGlobal, ElviMutable, ElviType, Variable, Variables};
-use pest_consume::{match_nodes, Error, Parser};
+use crate::internal::variables::{
+    quote, unescape, ElviGlobal, ElviMutable, ElviType, Escapes, Variable, Variables,
+};
+use crate::user_flags::Extension;
+use pest_consume::{match_nodes, Error, Itertools, Parser};
+use std::{iter, mem};
 
 #[derive(Parser)]
 #[grammar = "parse/internals/base.pest"]
@@ -18,7 +31,10 @@ use pest_consume::{match_nodes, Error, Parser};
 #[grammar = "parse/internals/commands.pest"]
 #[grammar = "parse/internals/if.pest"]
 #[grammar = "parse/internals/for.pest"]
+#[grammar = "parse/internals/while.pest"]
+#[grammar = "parse/internals/case.pest"]
 #[grammar = "parse/internals/functions.pest"]
+#[grammar = "parse/internals/redirections.pest"]
 /// Global struct that implements the pest.rs parser ([`pest_derive`]).
 pub struct ElviParser;
 
@@ -66,6 +82,35 @@ impl ElviParser {
         ))
     }
 
+    pub fn ansiCInner(input: Node) -> Result<String> {
+        Ok(input.as_str().to_string())
+    }
+
+    /// Handles `$'...'`, which is only a `$` in front of a single quoted string unless the
+    /// extension is turned on.
+    ///
+    /// Words are always text, so escapes that don't make valid UTF-8 are an error, rather than
+    /// being replaced with something else.
+    pub fn ansiCString(input: Node) -> Result<Vec<ElviType>> {
+        let enabled = input
+            .user_data()
+            .extensions
+            .contains(&Extension::AnsiCStrings);
+        let text = match_nodes!(input.clone().into_children();
+            [ansiCInner(text)] => text,
+        );
+        if !enabled {
+            return Ok(vec![
+                ElviType::BareString("$".into()),
+                ElviType::String(text),
+            ]);
+        }
+        let (bytes, _) = unescape(&text, Escapes::AnsiC);
+        String::from_utf8(bytes)
+            .map(|text| vec![ElviType::String(text)])
+            .map_err(|_| input.error("`$'...'` has to be valid UTF-8"))
+    }
+
     pub fn backtickInner(input: Node) -> Result<ElviType> {
         Ok(ElviType::CommandSubstitution(input.as_str().to_string()))
     }
@@ -77,15 +122,6 @@ impl ElviParser {
         ))
     }
 
-    /// Wrapper to handle any valid string.
-    pub fn anyString(input: Node) -> Result<ElviType> {
-        Ok(match_nodes!(input.into_children();
-            [singleQuoteString(stringo)] => stringo,
-            [doubleQuoteString(stringo)] => stringo,
-            [elviSingleWord(stringo)] => stringo,
-        ))
-    }
-
     pub fn builtinTestPrimaries(input: Node) -> Result<TestOptions> {
         Ok(match_nodes!(input.into_children();
             [block # elviWord(path)] => TestOptions::BlockFileExists(path),
@@ -141,18 +177,33 @@ impl ElviParser {
         Ok(ElviType::BareString(input.as_str().to_string()))
     }
 
-    /// Handles any single word
+    /// Handles any single word, which is only a [`ElviType::Word`] if it has more than one part.
     pub fn elviWord(input: Node) -> Result<ElviType> {
-        Ok(match_nodes!(input.into_children();
-            [anyString(stringo)] => stringo,
-            [elviSingleWord(stringo)] => stringo,
-        ))
+        let mut parts = vec![];
+        for child in input.into_children() {
+            match child.as_rule() {
+                Rule::singleQuoteString => parts.push(Self::singleQuoteString(child)?),
+                Rule::doubleQuoteString => parts.push(Self::doubleQuoteString(child)?),
+                Rule::ansiCString => parts.extend(Self::ansiCString(child)?),
+                _ => parts.push(Self::elviSingleWord(child)?),
+            }
+        }
+        Ok(if parts.len() == 1 {
+            parts.remove(0)
+        } else {
+            ElviType::Word(parts)
+        })
+    }
+
+    /// Handles the value of an assignment, which is built the same way as any other word.
+    pub fn assignedWord(input: Node) -> Result<ElviType> {
+        Self::elviWord(input)
     }
 
     /// Wrapper to handle any valid assignment of a variable.
     pub fn variableIdentifierPossibilities(input: Node) -> Result<ElviType> {
         Ok(match_nodes!(input.into_children();
-            [anyString(stringo)] => stringo,
+            [assignedWord(stringo)] => stringo,
             [backtickSubstitution(stringo)] => stringo,
         ))
     }
@@ -165,8 +216,13 @@ impl ElviParser {
 
         let name_pair = stuff.next().unwrap().as_str();
 
-        let variable_contents =
-            Self::variableIdentifierPossibilities(input.clone().into_children().nth(1).unwrap());
+        let variable_contents = input
+            .clone()
+            .into_children()
+            .nth(1)
+            .map_or(Ok(ElviType::String(String::new())), |possible| {
+                Self::variableIdentifierPossibilities(possible)
+            });
 
         Ok((
             name_pair.to_string(),
@@ -179,28 +235,6 @@ impl ElviParser {
         ))
     }
 
-    /// Handles readonly variable assignments.
-    pub fn readonlyVariable(input: Node) -> Result<(String, Variable)> {
-        let mut stuff = input.clone().into_children().into_pairs();
-
-        let lines = input.children().into_pairs().next().unwrap().line_col();
-
-        let name_pair = stuff.next().unwrap().as_str();
-
-        let variable_contents =
-            Self::variableIdentifierPossibilities(input.clone().into_children().nth(1).unwrap());
-
-        Ok((
-            name_pair.to_string(),
-            Variable {
-                contents: variable_contents.unwrap(),
-                shell_lvl: ElviGlobal::Normal(1),
-                modification_status: ElviMutable::Readonly,
-                line: lines,
-            },
-        ))
-    }
-
     /// Handles local variable assignments.
     pub fn localVariable(input: Node) -> Result<(String, Variable)> {
         let mut stuff = input.clone().into_children().into_pairs();
@@ -218,6 +252,7 @@ impl ElviParser {
                 contents: variable_contents.unwrap(),
                 shell_lvl: ElviGlobal::Local,
                 modification_status: ElviMutable::Normal,
+                exported: false,
                 line: lines,
             },
         ))
@@ -225,84 +260,124 @@ impl ElviParser {
 
     /// Handles the readonly builtin.
     pub fn builtinDbg(input: Node) -> Result<Actions> {
-        let possibles = match_nodes!(input.into_children();
-            [elviWord(stringo)..] => Some(stringo.collect()),
-            [] => None,
-        );
+        let (possibles, redirections) = builtin_arguments(input)?;
 
-        Ok(Actions::Builtin(Builtins::Dbg(possibles)))
+        Ok(Actions::Builtin(Builtins::Dbg(possibles)).redirect(redirections, vec![]))
     }
 
     /// Handles the unset builtin.
     pub fn builtinUnset(input: Node) -> Result<Actions> {
-        let possibles = match_nodes!(input.into_children();
-            [elviWord(stringo)..] => Some(stringo.collect()),
-            [] => None,
-        );
+        let (possibles, redirections) = builtin_arguments(input)?;
 
-        Ok(Actions::Builtin(Builtins::Unset(possibles)))
+        Ok(Actions::Builtin(Builtins::Unset(possibles)).redirect(redirections, vec![]))
     }
 
     /// Handles the echo builtin.
     pub fn builtinEcho(input: Node) -> Result<Actions> {
-        let possibles = match_nodes!(input.into_children();
-            [elviWord(stringo)..] => Some(stringo.collect()),
-            [] => None,
-        );
+        let (possibles, redirections) = builtin_arguments(input)?;
 
-        Ok(Actions::Builtin(Builtins::Echo(possibles)))
+        Ok(Actions::Builtin(Builtins::Echo(possibles)).redirect(redirections, vec![]))
     }
 
     /// Handles the exit builtin.
     pub fn builtinExit(input: Node) -> Result<Actions> {
-        let possibles = match_nodes!(input.into_children();
-            [elviWord(stringo)..] => Some(stringo.collect()),
-            [] => None,
-        );
+        let (possibles, redirections) = builtin_arguments(input)?;
+
+        Ok(Actions::Builtin(Builtins::Exit(possibles)).redirect(redirections, vec![]))
+    }
+
+    /// Handles the break builtin.
+    pub fn builtinBreak(input: Node) -> Result<Actions> {
+        let (possibles, redirections) = builtin_arguments(input)?;
+
+        Ok(Actions::Builtin(Builtins::Break(possibles)).redirect(redirections, vec![]))
+    }
 
-        Ok(Actions::Builtin(Builtins::Exit(possibles)))
+    /// Handles the continue builtin.
+    pub fn builtinContinue(input: Node) -> Result<Actions> {
+        let (possibles, redirections) = builtin_arguments(input)?;
+
+        Ok(Actions::Builtin(Builtins::Continue(possibles)).redirect(redirections, vec![]))
+    }
+
+    /// Handles the return builtin.
+    pub fn builtinReturn(input: Node) -> Result<Actions> {
+        let (possibles, redirections) = builtin_arguments(input)?;
+
+        Ok(Actions::Builtin(Builtins::Return(possibles)).redirect(redirections, vec![]))
     }
 
     /// Handles the hash builtin.
     pub fn builtinHash(input: Node) -> Result<Actions> {
-        let possibles = match_nodes!(input.into_children();
-            [elviWord(stringo)..] => Some(stringo.collect()),
-            [] => None,
-        );
+        let (possibles, redirections) = builtin_arguments(input)?;
 
-        Ok(Actions::Builtin(Builtins::Hash(possibles)))
+        Ok(Actions::Builtin(Builtins::Hash(possibles)).redirect(redirections, vec![]))
     }
 
     /// Handles the cd builtin.
     pub fn builtinCd(input: Node) -> Result<Actions> {
-        let possibles = match_nodes!(input.into_children();
-            [elviWord(stringo)..] => Some(stringo.collect()),
-            [] => None,
-        );
+        let (possibles, redirections) = builtin_arguments(input)?;
 
-        Ok(Actions::Builtin(Builtins::Cd(possibles)))
+        Ok(Actions::Builtin(Builtins::Cd(possibles)).redirect(redirections, vec![]))
     }
 
-    /// Handles the cd builtin.
+    /// Handles the shift builtin.
     pub fn builtinShift(input: Node) -> Result<Actions> {
-        let possibles = match_nodes!(input.into_children();
-            [elviWord(stringo)..] => Some(stringo.collect()),
-            [] => None,
-        );
+        let (possibles, redirections) = builtin_arguments(input)?;
+
+        Ok(Actions::Builtin(Builtins::Shift(possibles)).redirect(redirections, vec![]))
+    }
+
+    /// Handles the set builtin.
+    pub fn builtinSet(input: Node) -> Result<Actions> {
+        let (possibles, redirections) = builtin_arguments(input)?;
+
+        Ok(Actions::Builtin(Builtins::Set(possibles)).redirect(redirections, vec![]))
+    }
+
+    /// Handles the wait builtin.
+    pub fn builtinWait(input: Node) -> Result<Actions> {
+        let (possibles, redirections) = builtin_arguments(input)?;
+
+        Ok(Actions::Builtin(Builtins::Wait(possibles)).redirect(redirections, vec![]))
+    }
 
-        Ok(Actions::Builtin(Builtins::Shift(possibles)))
+    /// Handles the export builtin.
+    pub fn builtinExport(input: Node) -> Result<Actions> {
+        let (possibles, assignments, redirections) = declaration_arguments(input)?;
+
+        Ok(Actions::Builtin(Builtins::Export(possibles, assignments))
+            .redirect(redirections, vec![]))
+    }
+
+    /// Handles the readonly builtin, which remembers where it is so that errors can point to it.
+    pub fn builtinReadonly(input: Node) -> Result<Actions> {
+        let position = input.as_span().start_pos().line_col();
+        let (possibles, assignments, redirections) = declaration_arguments(input)?;
+
+        Ok(
+            Actions::Builtin(Builtins::Readonly(possibles, assignments, position))
+                .redirect(redirections, vec![]),
+        )
     }
 
     pub fn builtinWrapper(input: Node) -> Result<Actions> {
         Ok(match_nodes!(input.into_children();
             [builtinDbg(s)] =>   s,
             [builtinExit(s)] =>  s,
+            [builtinBreak(s)] => s,
+            [builtinContinue(s)] => s,
+            [builtinReturn(s)] => s,
             [builtinUnset(s)] => s,
             [builtinHash(s)] =>  s,
             [builtinCd(s)] =>    s,
             [builtinTest(s)] =>  s,
             [builtinEcho(s)] =>  s,
             [builtinShift(s)] => s,
+            [builtinWait(s)] =>  s,
+            [builtinSet(s)] =>   s,
+            [builtinExport(s)] => s,
+            [builtinReadonly(s)] => s,
         ))
     }
 
@@ -322,6 +397,8 @@ impl ElviParser {
     pub fn compoundCommands(input: Node) -> Result<Actions> {
         Ok(match_nodes!(input.into_children();
             [forLoop(stmt)] => stmt,
+            [whileLoop(stmt)] => stmt,
+            [caseStatement(stmt)] => stmt,
             [ifStatement(stmt)] => stmt,
             [compoundShell(stmt)] => Actions::CompoundBrackets(stmt),
             [compoundSubshell(stmt)] => Actions::Subshell(stmt),
@@ -330,18 +407,102 @@ impl ElviParser {
 
     /// Handles any external command.
     pub fn externalCommand(input: Node) -> Result<Actions> {
+        let (words, redirections) = words_and_redirections(input)?;
+        Ok(Actions::Command(words).redirect(redirections, vec![]))
+    }
+
+    /// Handles the file descriptor of a redirection.
+    pub fn redirectionFd(input: Node) -> Result<i32> {
+        input
+            .as_str()
+            .parse()
+            .map_err(|_| input.error("file descriptor out of range"))
+    }
+
+    /// Handles the operator of a redirection.
+    pub fn redirectionOperator(input: Node) -> Result<RedirectionKind> {
+        Ok(match input.as_str() {
+            "<" => RedirectionKind::Input,
+            ">" => RedirectionKind::Output,
+            ">|" => RedirectionKind::Clobber,
+            ">>" => RedirectionKind::Append,
+            "<&" => RedirectionKind::DuplicateInput,
+            ">&" => RedirectionKind::DuplicateOutput,
+            "<>" => RedirectionKind::ReadWrite,
+            _ => unreachable!("The grammar only allows the operators above"),
+        })
+    }
+
+    /// Handles redirections.
+    pub fn redirection(input: Node) -> Result<Redirection> {
+        Ok(match_nodes!(input.into_children();
+            [redirectionFd(fd), redirectionOperator(kind), elviWord(target)] => Redirection { fd, kind, target },
+            [redirectionOperator(kind), elviWord(target)] => Redirection { fd: kind.default_fd(), kind, target },
+            [hereDocument(redirection)] => redirection,
+        ))
+    }
+
+    /// Handles here-documents.
+    pub fn hereDocument(input: Node) -> Result<Redirection> {
+        let mut fd = libc::STDIN_FILENO;
+        let mut kind = RedirectionKind::HereDocument { strip_tabs: false };
+        let mut target = ElviType::String(String::new());
+        for child in input.into_children() {
+            match child.as_rule() {
+                Rule::redirectionFd => fd = Self::redirectionFd(child)?,
+                Rule::hereDocumentOperator => {
+                    kind = RedirectionKind::HereDocument {
+                        strip_tabs: child.as_str() == "<<-",
+                    };
+                }
+                _ => {
+                    // A quoted delimiter keeps the body exactly as is.
+                    target = match Self::hereDocumentDelimiter(child)? {
+                        (delimiter, true) => ElviType::String(delimiter),
+                        (delimiter, false) => ElviType::VariableSubstitution(delimiter),
+                    };
+                }
+            }
+        }
+        Ok(Redirection { fd, kind, target })
+    }
+
+    /// Handles the delimiter of a here-document.
+    ///
+    /// # Returns
+    /// The delimiter with its quotes removed, and whether any part of it was quoted.
+    pub fn hereDocumentDelimiter(input: Node) -> Result<(String, bool)> {
+        let mut delimiter = String::new();
+        let mut quoted = false;
+        for child in input.into_children() {
+            quoted |= child.as_rule() != Rule::unquotedDelimiter;
+            delimiter.push_str(child.as_str());
+        }
+        // Empty double quotes like `<<""` don't leave anything behind to show they were there.
+        quoted |= delimiter.is_empty();
+        Ok((delimiter, quoted))
+    }
+
+    /// Handles the bodies of here-documents.
+    pub fn hereDocumentBodies(input: Node) -> Result<String> {
         Ok(match_nodes!(input.into_children();
-            [elviWord(yep)..] => Actions::Command(yep.collect()),
+            [hereDocumentBody(body)] => body,
         ))
     }
 
+    /// Handles the lines of here-documents.
+    pub fn hereDocumentBody(input: Node) -> Result<String> {
+        Ok(input.as_str().to_string())
+    }
+
     /// Handles function statements.
     pub fn functionDeclaration(input: Node) -> Result<Actions> {
         Ok(match_nodes!(input.into_children();
-            [name # variableIdent(name), inner_function # compoundCommands(stmt)..] => {
+            [name # variableIdent(name), inner_function # compoundCommands(stmt), redirection(redirections)..] => {
                 Actions::FunctionDeclaration(Function {
                     name,
-                    contents: Some(stmt.collect()),
+                    // Redirections on a function declaration are applied every time it is run.
+                    contents: Some(vec![stmt.redirect(vec![], redirections.collect())]),
                 })
             },
             [name # variableIdent(name)] => {
@@ -355,37 +516,108 @@ impl ElviParser {
         ))
     }
 
-    /// Handles if statement conditions
-    pub fn ifStatementMatch(input: Node) -> Result<Actions> {
+    /// Handles a single `elif` branch.
+    pub fn elifBlock(input: Node) -> Result<Conditional> {
         Ok(match_nodes!(input.into_children();
-            [builtinWrapper(built)] => built,
-            [externalCommand(cmd)] => cmd,
+            [condition # statement(condition).., then_block # statement(stmt)..] => Conditional {
+                condition: Actions::CompoundBrackets(condition.collect()),
+                then_block: stmt.collect(),
+                elif_block: None,
+                else_block: None,
+            },
         ))
     }
 
+    /// Handles the keyword starting an else branch.
+    pub fn elseKeyword(input: Node) -> Result<()> {
+        if input.as_str() == "elsie" && !input.user_data().extensions.contains(&Extension::Elsie) {
+            return Err(input.error("`elsie` needs `--extension elsie`, use `else` instead"));
+        }
+        Ok(())
+    }
+
     /// Handles if statements.
     pub fn ifStatement(input: Node) -> Result<Actions> {
+        let mut condition = vec![];
+        let mut then_block = vec![];
+        let mut elif_block = vec![];
+        let mut else_block = None;
+        for child in input.into_children() {
+            match (child.as_pair().as_node_tag(), child.as_rule()) {
+                (Some("condition"), _) => condition.push(Self::statement(child)?),
+                (Some("then_block"), _) => then_block.push(Self::statement(child)?),
+                (Some("else_block"), _) => else_block
+                    .get_or_insert_with(Vec::new)
+                    .push(Self::statement(child)?),
+                (_, Rule::elifBlock) => elif_block.push(Self::elifBlock(child)?),
+                _ => Self::elseKeyword(child)?,
+            }
+        }
+        Ok(Actions::IfStatement(Box::new(Conditional {
+            // A list of conditions is run just like a group.
+            condition: Actions::CompoundBrackets(condition),
+            then_block,
+            elif_block: (!elif_block.is_empty()).then_some(elif_block),
+            else_block,
+        })))
+    }
+
+    /// Handles the kind of a conditional loop, returning `true` for `until`.
+    pub fn whileKind(input: Node) -> Result<bool> {
+        Ok(input.as_str() == "until")
+    }
+
+    /// Handles while and until loops.
+    pub fn whileLoop(input: Node) -> Result<Actions> {
         Ok(match_nodes!(input.into_children();
-            // Condition + then_block
-            [ifStatementMatch(condition), then_block # statement(stmt)..] => Actions::IfStatement(Box::new(
-                Conditional {
-                    condition,
-                    then_block: stmt.collect(),
-                    elif_block: None,
-                    else_block: None
-                }
-            ),
-        ),
-            // Condition + then_block + else_block
-            [ifStatementMatch(condition), then_block # statement(stmt).., else_block # statement(else_part)..] => Actions::IfStatement(Box::new(
-                    Conditional {
-                        condition,
-                        then_block: stmt.collect(),
-                        elif_block: None,
-                        else_block: Some(else_part.collect())
-                    }
-                ),
-            )
+            [whileKind(until), condition # statement(condition).., inner_while # statement(stmt)..] => {
+                let looped = ConditionalLoop {
+                    condition: condition.collect(),
+                    do_block: stmt.collect(),
+                };
+                if until {
+                    Actions::UntilLoop(looped)
+                } else {
+                    Actions::WhileLoop(looped)
+                }
+            },
+        ))
+    }
+
+    /// Handles the unquoted parts of a case pattern.
+    pub fn casePatternWord(input: Node) -> Result<ElviType> {
+        Ok(ElviType::BareString(input.as_str().to_string()))
+    }
+
+    /// Handles a single case pattern.
+    pub fn casePattern(input: Node) -> Result<Vec<ElviType>> {
+        input
+            .into_children()
+            .map(|child| match child.as_rule() {
+                Rule::singleQuoteString => Self::singleQuoteString(child),
+                Rule::doubleQuoteString => Self::doubleQuoteString(child),
+                _ => Self::casePatternWord(child),
+            })
+            .collect()
+    }
+
+    /// Handles a single branch of a case statement.
+    pub fn caseItem(input: Node) -> Result<CaseItem> {
+        Ok(match_nodes!(input.into_children();
+            [casePattern(patterns).., statement(stmt)..] => CaseItem {
+                patterns: patterns.collect(),
+                body: stmt.collect(),
+            },
+        ))
+    }
+
+    /// Handles case statements.
+    pub fn caseStatement(input: Node) -> Result<Actions> {
+        Ok(match_nodes!(input.into_children();
+            [elviWord(word), caseItem(items)..] => Actions::CaseStatement(Case {
+                word,
+                items: items.collect(),
+            }),
         ))
     }
 
@@ -400,18 +632,37 @@ impl ElviParser {
     /// Handles for loops.
     pub fn forLoop(input: Node) -> Result<Actions> {
         Ok(match_nodes!(input.into_children();
-            // When we have loop contents
-            [variable # elviWord(var), loop_match # forLoopMatch(loop_match).., inner_for # statement(stmt)..] => Actions::ForLoop(Loop { variable: var, elements: loop_match.collect(), do_block: stmt.collect() }),
-            // When we don't
-            [variable # elviWord(var), inner_for # statement(stmt)..] => Actions::ForLoop(Loop { variable: var, elements: vec![ElviType::VariableSubstitution("${@}".to_string())], do_block: stmt.collect() })
+            // When we don't have loop contents, which has to go first since the other one matches
+            // no contents too.
+            [variable # elviWord(var), inner_for # statement(stmt)..] => Actions::ForLoop(Loop { variable: var, elements: None, do_block: stmt.collect() }),
+            // When we do
+            [variable # elviWord(var), loop_match # forLoopMatch(loop_match).., inner_for # statement(stmt)..] => Actions::ForLoop(Loop { variable: var, elements: Some(loop_match.collect()), do_block: stmt.collect() })
         ))
     }
 
-    /// Handles global statements.
-    pub fn statement(input: Node) -> Result<Actions> {
+    /// Handles variable assignments, along with the simple command they are in front of.
+    ///
+    /// A single assignment by itself is just that assignment.
+    pub fn assignedCommand(input: Node) -> Result<Actions> {
+        let mut assignments = vec![];
+        let mut command = None;
+        for child in input.into_children() {
+            match child.as_rule() {
+                Rule::normalVariable => assignments.push(Self::normalVariable(child)?),
+                Rule::builtinWrapper => command = Some(Self::builtinWrapper(child)?),
+                _ => command = Some(Self::externalCommand(child)?),
+            }
+        }
+        Ok(match command {
+            None if assignments.len() == 1 => Actions::ChangeVariable(assignments.remove(0)),
+            command => Actions::Assigned(assignments, Box::new(command.unwrap_or(Actions::Null))),
+        })
+    }
+
+    /// Handles any single command.
+    pub fn elviCommand(input: Node) -> Result<Actions> {
         match_nodes!(input.into_children();
-            [normalVariable(var)] => Ok(Actions::ChangeVariable(var)),
-            [readonlyVariable(var)] => Ok(Actions::ChangeVariable(var)),
+            [assignedCommand(cmd)] => Ok(cmd),
             [localVariable(var)] => Ok(Actions::ChangeVariable(var)),
             [builtinWrapper(var)] => Ok(var),
             [externalCommand(var)] => Ok(var),
@@ -420,13 +671,105 @@ impl ElviParser {
         )
     }
 
+    /// Handles a single stage of a pipeline, along with the redirections around it.
+    pub fn pipelineCommand(input: Node) -> Result<Actions> {
+        let mut before = vec![];
+        let mut after = vec![];
+        let mut command = None;
+        for child in input.into_children() {
+            match child.as_rule() {
+                Rule::redirection if command.is_none() => before.push(Self::redirection(child)?),
+                Rule::redirection => after.push(Self::redirection(child)?),
+                _ => command = Some(Self::elviCommand(child)?),
+            }
+        }
+        Ok(command.unwrap_or(Actions::Null).redirect(before, after))
+    }
+
+    /// Handles pipelines.
+    ///
+    /// A pipeline with only one command is just that command.
+    pub fn pipeline(input: Node) -> Result<Actions> {
+        Ok(match_nodes!(input.into_children();
+            [pipelineCommand(cmd)] => cmd,
+            [pipelineCommand(cmds)..] => Actions::Pipeline(cmds.collect()),
+            [negation(_), pipelineCommand(cmd)] => Actions::Not(Box::new(cmd)),
+            [negation(_), pipelineCommand(cmds)..] => Actions::Not(Box::new(Actions::Pipeline(cmds.collect()))),
+        ))
+    }
+
+    #[allow(clippy::used_underscore_binding)]
+    /// Handles the `!` in front of a pipeline.
+    pub fn negation(_input: Node) -> Result<()> {
+        Ok(())
+    }
+
+    /// Handles `&&` and `||`, returning `true` for `&&`.
+    pub fn andOrOperator(input: Node) -> Result<bool> {
+        Ok(input.as_str() == "&&")
+    }
+
+    /// Handles and-or lists.
+    ///
+    /// These are left associative, so `foo && bar || baz` runs `baz` if either `foo` or `bar`
+    /// fail.
+    pub fn andOrList(input: Node) -> Result<Actions> {
+        let mut children = input.into_children();
+        // The grammar always starts with a pipeline.
+        let mut list = Self::pipeline(children.next().unwrap())?;
+        while let (Some(operator), Some(next)) = (children.next(), children.next()) {
+            list = if Self::andOrOperator(operator)? {
+                Actions::And(Box::new(list), Box::new(Self::pipeline(next)?))
+            } else {
+                Actions::Or(Box::new(list), Box::new(Self::pipeline(next)?))
+            };
+        }
+        Ok(list)
+    }
+
+    /// Handles the `&` after a statement.
+    #[allow(clippy::used_underscore_binding)]
+    pub fn background(_input: Node) -> Result<()> {
+        Ok(())
+    }
+
+    /// Handles global statements.
+    pub fn statement(input: Node) -> Result<Actions> {
+        let (mut stmt, background, bodies) = match_nodes!(input.children();
+            [andOrList(stmt)] => (stmt, false, String::new()),
+            [andOrList(stmt), background(())] => (stmt, true, String::new()),
+            [andOrList(stmt), hereDocumentBodies(bodies)] => (stmt, false, bodies),
+            [andOrList(stmt), background(()), hereDocumentBodies(bodies)] => (stmt, true, bodies),
+        );
+        read_here_documents(&mut stmt, &bodies).map_err(|delimiter| {
+            input.error(format!(
+                "here-document is missing its delimiter `{delimiter}`"
+            ))
+        })?;
+        // Leading blank lines are part of the statement, so the line comes from what follows them.
+        let line = input
+            .children()
+            .next()
+            .map_or(0, |list| list.as_span().start_pos().line_col().0);
+        if background {
+            stmt = Actions::Background(Box::new(stmt));
+        }
+        Ok(Actions::Statement(line, Box::new(stmt)))
+    }
+
     /// Entry point for parsing.
     pub fn program(input: Node) -> ReturnCode {
+        let positional_arguments = input.user_data();
         let mut variables = Variables::default();
+        variables.import_environment(positional_arguments.environment.as_deref());
+        variables.fill_defaults();
+        // Commands are looked up in the `$PATH` that was just imported.
         let mut commands = Commands::generate(&variables);
         let mut global_env = Env::default();
 
-        let positional_arguments = input.user_data();
+        global_env
+            .extensions
+            .clone_from(&positional_arguments.extensions);
 
         // Set all the positional variables once.
         let list: Vec<Variable> = positional_arguments
@@ -435,29 +778,309 @@ impl ElviParser {
             .map(|var| var.to_owned().into())
             .collect();
         variables.new_parameters(&list);
+        variables.set_special("$", ElviType::String(std::process::id().to_string()));
+        variables.set_special("-", ElviType::String(positional_arguments.flags.clone()));
 
-        for child in input.into_children() {
-            if child.as_rule() != Rule::EOI {
-                match Self::statement(child) {
-                    Ok(yes) => {
-                        eval(yes, &mut variables, &mut commands, &mut global_env);
-                    }
-                    Err(oops) => {
-                        eprintln!("{oops}");
-                        continue;
+        eval_statements(input, true, &mut variables, &mut commands, &mut global_env);
+
+        ReturnCode::ret(variables.get_ret().convert_err_type().get())
+    }
+}
+
+/// Parse and evaluate every statement of a program, one at a time.
+///
+/// With `-v`, every statement of the `input` of the shell is printed before it's run, and with
+/// `-n`, nothing is run at all.
+///
+/// # Notes
+/// A statement that can't be handled is reported and skipped, while the rest still run.
+fn eval_statements(
+    input: Node,
+    is_input: bool,
+    variables: &mut Variables,
+    commands: &mut Commands,
+    global_env: &mut Env,
+) {
+    for child in input.into_children() {
+        if child.as_rule() != Rule::EOI {
+            if is_input && variables.has_flag('v') {
+                eprintln!("{}", child.as_str().trim_start_matches('\n'));
+            }
+            match ElviParser::statement(child) {
+                Ok(_) if variables.has_flag('n') => {}
+                Ok(yes) => {
+                    eval(yes, variables, commands, global_env);
+                    // Only `return` and `exit` get this far, and they end the program.
+                    if global_env.flow.is_some() {
+                        break;
                     }
                 }
+                Err(oops) => {
+                    eprintln!("{oops}");
+                    continue;
+                }
             }
         }
+    }
+}
 
-        ReturnCode::ret(variables.get_ret().convert_err_type().get())
+/// Parse and evaluate Elvi code in the current environment, such as the body of a command
+/// substitution.
+///
+/// # Returns
+/// The return code of the last statement, or [`ReturnCode::MISUSE`] if the code doesn't parse.
+pub fn eval_code(
+    code: &str,
+    variables: &mut Variables,
+    commands: &mut Commands,
+    global_env: &mut Env,
+) -> ReturnCode {
+    // `program` needs at least one statement.
+    if code.trim().is_empty() {
+        return ReturnCode::SUCCESS.into();
+    }
+    let arguments = Arguments {
+        args: vec![],
+        extensions: global_env.extensions.clone(),
+        flags: String::new(),
+        environment: None,
+    };
+    let parsed = match ElviParser::parse_with_userdata(Rule::program, code, &arguments)
+        .and_then(|nodes| nodes.single())
+    {
+        Ok(yay) => yay,
+        Err(oops) => {
+            eprintln!("{oops}");
+            return ReturnCode::MISUSE.into();
+        }
+    };
+    eval_statements(parsed, false, variables, commands, global_env);
+    ReturnCode::ret(variables.get_ret().convert_err_type().get())
+}
+
+/// Split the children of a simple command into its words and its redirections.
+fn words_and_redirections(input: Node) -> Result<(Vec<ElviType>, Vec<Redirection>)> {
+    let mut words = vec![];
+    let mut redirections = vec![];
+    for child in input.into_children() {
+        match child.as_rule() {
+            Rule::redirection => redirections.push(ElviParser::redirection(child)?),
+            _ => words.push(ElviParser::elviWord(child)?),
+        }
+    }
+    Ok((words, redirections))
+}
+
+/// Same as [`words_and_redirections`], but with no words being [`None`] like builtins expect.
+fn builtin_arguments(input: Node) -> Result<(Option<Vec<ElviType>>, Vec<Redirection>)> {
+    let (words, redirections) = words_and_redirections(input)?;
+    Ok(((!words.is_empty()).then_some(words), redirections))
+}
+
+/// The names or options, assignments, and redirections given to a builtin like `export` or
+/// `readonly`.
+type DeclarationArguments = (
+    Option<Vec<ElviType>>,
+    Vec<(String, Variable)>,
+    Vec<Redirection>,
+);
+
+/// Same as [`builtin_arguments`], but for builtins like `export` that can also take assignments.
+fn declaration_arguments(input: Node) -> Result<DeclarationArguments> {
+    let mut words = vec![];
+    let mut assignments = vec![];
+    let mut redirections = vec![];
+    for child in input.into_children() {
+        match child.as_rule() {
+            Rule::redirection => redirections.push(ElviParser::redirection(child)?),
+            Rule::normalVariable => assignments.push(ElviParser::normalVariable(child)?),
+            _ => words.push(ElviParser::elviWord(child)?),
+        }
+    }
+    Ok((
+        (!words.is_empty()).then_some(words),
+        assignments,
+        redirections,
+    ))
+}
+
+/// Split the lines following a statement between its here-documents, replacing their delimiters.
+///
+/// The last here-document gets whatever is left, since the grammar already found its delimiter.
+///
+/// # Errors
+/// Will return the delimiter of a here-document that never ends.
+fn read_here_documents(stmt: &mut Actions, bodies: &str) -> std::result::Result<(), String> {
+    let mut lines = bodies.lines();
+    let mut docs = stmt.here_documents_mut();
+    let last = docs.len().saturating_sub(1);
+    for (idx, doc) in docs.iter_mut().enumerate() {
+        let RedirectionKind::HereDocument { strip_tabs } = doc.kind else {
+            continue;
+        };
+        let delimiter = doc.target.to_string();
+        let mut body = String::new();
+        loop {
+            let line = match lines.next() {
+                Some(line) if strip_tabs => line.trim_start_matches('\t'),
+                Some(line) => line,
+                None if idx == last => break,
+                None => return Err(delimiter),
+            };
+            if idx != last && line == delimiter {
+                break;
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+        doc.target = match doc.target {
+            ElviType::VariableSubstitution(_) => ElviType::VariableSubstitution(body),
+            _ => ElviType::String(body),
+        };
+    }
+    Ok(())
+}
+
+/// Evaluates a [`ConditionalLoop`], running it while the condition succeeds, or while it fails for
+/// `until`.
+///
+/// # Returns
+/// The return code of the last action in the loop body, or success if it never ran.
+fn eval_conditional_loop(
+    loop_things: &ConditionalLoop,
+    until: bool,
+    variables: &mut Variables,
+    commands: &mut Commands,
+    global_env: &mut Env,
+) -> ReturnCode {
+    let mut ret: ReturnCode = ReturnCode::SUCCESS.into();
+    global_env.loops += 1;
+    loop {
+        // Run the condition, where `-e` doesn't apply.
+        global_env.errexit_ignored += 1;
+        eval_block(&loop_things.condition, variables, commands, global_env);
+        global_env.errexit_ignored -= 1;
+        if global_env.end_iteration() {
+            break;
+        }
+        // Did we succeed?
+        let succeeded = variables.get_ret().convert_err_type().get() == ReturnCode::SUCCESS;
+        if succeeded == until {
+            break;
+        }
+        ret = eval_block(&loop_things.do_block, variables, commands, global_env);
+        if global_env.end_iteration() {
+            break;
+        }
+    }
+    global_env.loops -= 1;
+    ret
+}
+
+/// Evaluates a list of [`Actions`] in order, stopping early for `break`, `continue`, `return` and
+/// `exit`.
+///
+/// # Returns
+/// The return code of the last action run, or success if there weren't any.
+fn eval_block(
+    block: &[Actions],
+    variables: &mut Variables,
+    commands: &mut Commands,
+    global_env: &mut Env,
+) -> ReturnCode {
+    let mut ret: ReturnCode = ReturnCode::SUCCESS.into();
+    for act in block {
+        ret = eval(act.to_owned(), variables, commands, global_env);
+        variables.set_ret(ret);
+        if global_env.flow.is_some() {
+            break;
+        }
     }
+    ret
 }
 
 /// Evaluates any given [`Actions`].
+///
+/// With `-e`, the shell is left as soon as a simple command, pipeline or subshell fails, unless
+/// that happens somewhere it's ignored, according to
+/// <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#set>.
+pub fn eval(
+    action: Actions,
+    variables: &mut Variables,
+    commands: &mut Commands,
+    global_env: &mut Env,
+) -> ReturnCode {
+    let checked = errexit_applies(&action);
+    let ret = eval_action(action, variables, commands, global_env);
+    if checked {
+        errexit(ret, variables, global_env);
+    }
+    ret
+}
+
+/// Whether `-e` is checked once an action is run, which isn't the case for compound commands,
+/// since they fail because of something in them that already was.
+///
+/// Redirected commands are checked after their redirections are undone, so that the error goes
+/// where it should.
+fn errexit_applies(action: &Actions) -> bool {
+    match action {
+        Actions::Redirect(inner, _) => errexit_applies(inner),
+        // A command after the assignments is checked on its own, but assignments on their own
+        // fail with their last command substitution.
+        Actions::Assigned(_, inner) => matches!(**inner, Actions::Null),
+        Actions::Command(_)
+        | Actions::Builtin(_)
+        | Actions::ChangeVariable(_)
+        | Actions::Pipeline(_)
+        | Actions::Subshell(_) => true,
+        _ => false,
+    }
+}
+
+/// Same as [`eval`], but `-e` is ignored for all of it, like in the condition of an `if`, the left
+/// side of `&&` and `||`, and after `!`.
+fn eval_ignoring_errexit(
+    action: Actions,
+    variables: &mut Variables,
+    commands: &mut Commands,
+    global_env: &mut Env,
+) -> ReturnCode {
+    global_env.errexit_ignored += 1;
+    let ret = eval(action, variables, commands, global_env);
+    global_env.errexit_ignored -= 1;
+    ret
+}
+
+/// Leave the shell if `-e` is on and `ret` is a failure, unless it's being ignored right now or
+/// something is already being left.
+///
+/// With the `verbose-errors` extension, the line that failed is printed first.
+fn errexit(ret: ReturnCode, variables: &Variables, global_env: &mut Env) {
+    if ret.get() == ReturnCode::SUCCESS
+        || global_env.errexit_ignored > 0
+        || global_env.flow.is_some()
+        || !variables.has_flag('e')
+    {
+        return;
+    }
+    if global_env.extensions.contains(&Extension::VerboseErrors) {
+        let line = variables
+            .get_variable("LINENO")
+            .map(|line| line.contents.to_string())
+            .unwrap_or_default();
+        eprintln!(
+            "elvi: line {line}: exiting with status {} because of `set -e`",
+            ret.get()
+        );
+    }
+    global_env.flow = Some(ControlFlow::Exit);
+}
+
+/// Evaluates any given [`Actions`], for [`eval`].
 // We know clippy. Lol.
 #[allow(clippy::too_many_lines)]
-pub fn eval(
+fn eval_action(
     action: Actions,
     variables: &mut Variables,
     commands: &mut Commands,
@@ -466,53 +1089,160 @@ pub fn eval(
     match action {
         Actions::ChangeVariable((name, mut var)) => {
             change_variable(variables, commands, global_env, &name, &mut var);
-        }
-        Actions::Builtin(built) => match built {
-            Builtins::Dbg(var) => {
-                let ret = builtins::dbg::dbg::main(var.as_deref(), variables);
-                variables.set_ret(ret);
+            // The expansion failed, so the shell is on its way out.
+            if global_env.flow.is_some() {
+                return variables.get_ret().convert_err_type();
             }
-            Builtins::Exit(var) => {
-                let ret = builtins::exit::exit::main(var.as_deref(), variables);
-                if global_env.update_shlvl(-1, variables) < 1 {
-                    std::process::exit(ret.get().into());
-                } else {
-                    variables.set_ret(ret);
+            if variables.has_flag('x') {
+                let value = variables
+                    .get_variable(&name)
+                    .map(|var| var.contents.to_string())
+                    .unwrap_or_default();
+                trace(&format!("{name}={}", trace_word(&value)), variables);
+            }
+        }
+        Actions::Builtin(mut built) => {
+            if let Some(words) = built.arguments_mut() {
+                let mut fields = vec![];
+                for word in words.iter().flatten() {
+                    let (expanded, _) = expand_word(word, variables, commands, global_env);
+                    if global_env.flow.is_some() {
+                        return variables.get_ret().convert_err_type();
+                    }
+                    fields.extend(expanded.into_iter().map(ElviType::String));
                 }
+                *words = (!fields.is_empty()).then_some(fields);
             }
-            Builtins::Unset(var) => {
-                let ret = builtins::unset::unset::main(var.as_deref(), variables, commands);
-                variables.set_ret(ret);
+            for word in built.words_mut() {
+                expand_substitutions(word, variables, commands, global_env);
+                if global_env.flow.is_some() {
+                    return variables.get_ret().convert_err_type();
+                }
             }
-            Builtins::Hash(flag) => {
-                let ret = builtins::hash::hash::main(flag.as_deref(), commands, variables);
-                variables.set_ret(ret);
+            if variables.has_flag('x') {
+                trace_builtin(&mut built, variables);
             }
-            Builtins::Cd(flag) => {
-                let ret = builtins::cd::cd::main(flag.as_deref(), variables);
-                variables.set_ret(ret);
-            }
-            Builtins::Test(invert, yo) => {
-                let ret = builtins::test::test::main(invert, yo, variables);
-                variables.set_ret(ret);
-            }
-            Builtins::Echo(text) => {
-                let ret = builtins::echo::echo::main(text.as_deref(), variables);
-                variables.set_ret(ret);
-            }
-            Builtins::Shift(text) => {
-                let ret = builtins::shift::shift::main(text.as_deref(), variables);
-                variables.set_ret(ret);
+            match built {
+                Builtins::Dbg(var) => {
+                    let ret = builtins::dbg::dbg::main(var.as_deref(), variables, global_env);
+                    variables.set_ret(ret);
+                }
+                Builtins::Exit(var) => {
+                    let ret = builtins::exit::exit::main(var.as_deref(), variables);
+                    variables.set_ret(ret);
+                    global_env.flow = Some(ControlFlow::Exit);
+                }
+                Builtins::Break(levels) => {
+                    let ret = builtins::flow::flow::loops(false, levels.as_deref(), global_env);
+                    variables.set_ret(ret);
+                }
+                Builtins::Continue(levels) => {
+                    let ret = builtins::flow::flow::loops(true, levels.as_deref(), global_env);
+                    variables.set_ret(ret);
+                }
+                Builtins::Return(code) => {
+                    let ret = builtins::flow::flow::ret(code.as_deref(), variables, global_env);
+                    variables.set_ret(ret);
+                }
+                Builtins::Unset(var) => {
+                    let ret = builtins::unset::unset::main(var.as_deref(), variables, commands);
+                    variables.set_ret(ret);
+                }
+                Builtins::Hash(flag) => {
+                    let ret = builtins::hash::hash::main(
+                        flag.as_deref(),
+                        commands,
+                        variables,
+                        global_env,
+                    );
+                    variables.set_ret(ret);
+                }
+                Builtins::Cd(flag) => {
+                    let ret = builtins::cd::cd::main(flag.as_deref(), variables, global_env);
+                    variables.set_ret(ret);
+                }
+                Builtins::Test(invert, yo) => {
+                    let ret = builtins::test::test::main(invert, yo, variables);
+                    variables.set_ret(ret);
+                }
+                Builtins::Echo(text) => {
+                    let ret = builtins::echo::echo::main(text.as_deref(), variables, global_env);
+                    variables.set_ret(ret);
+                }
+                Builtins::Shift(text) => {
+                    let ret = builtins::shift::shift::main(text.as_deref(), variables);
+                    variables.set_ret(ret);
+                }
+                Builtins::Set(options) => {
+                    let ret = builtins::set::set::main(options.as_deref(), variables, global_env);
+                    variables.set_ret(ret);
+                }
+                Builtins::Export(names, assignments) => {
+                    // Only `export` on its own lists what's exported.
+                    let listing = assignments.is_empty();
+                    for (name, mut var) in assignments {
+                        change_variable(variables, commands, global_env, &name, &mut var);
+                        if global_env.flow.is_some() {
+                            return variables.get_ret().convert_err_type();
+                        }
+                        variables.export(&name);
+                    }
+                    let ret = if names.is_some() || listing {
+                        builtins::export::export::main(names.as_deref(), variables, global_env)
+                    } else {
+                        ReturnCode::SUCCESS.into()
+                    };
+                    variables.set_ret(ret);
+                }
+                Builtins::Readonly(names, assignments, position) => {
+                    // Only `readonly` on its own lists what's readonly.
+                    let listing = assignments.is_empty();
+                    for (name, mut var) in assignments {
+                        var.modification_status = ElviMutable::Readonly;
+                        change_variable(variables, commands, global_env, &name, &mut var);
+                        if global_env.flow.is_some() {
+                            return variables.get_ret().convert_err_type();
+                        }
+                    }
+                    let ret = if names.is_some() || listing {
+                        builtins::readonly::readonly::main(
+                            names.as_deref(),
+                            position,
+                            variables,
+                            global_env,
+                        )
+                    } else {
+                        ReturnCode::SUCCESS.into()
+                    };
+                    variables.set_ret(ret);
+                }
+                Builtins::Wait(pids) => {
+                    let ret = builtins::wait::wait::main(pids.as_deref(), variables, global_env);
+                    variables.set_ret(ret);
+                }
             }
-        },
+        }
         Actions::Command(cmd) => {
             let mut expanded = vec![];
+            let mut status = None;
             for part in cmd {
-                expanded.push(
-                    part.tilde_expansion(variables)
-                        .eval_variables(variables)
-                        .eval_escapes()
-                        .to_string(),
+                let (fields, ret) = expand_word(&part, variables, commands, global_env);
+                if global_env.flow.is_some() {
+                    return variables.get_ret().convert_err_type();
+                }
+                expanded.extend(fields);
+                status = ret.or(status);
+            }
+            // Everything could have expanded to nothing, which still sets `$?` for any command
+            // substitutions that ran.
+            if expanded.is_empty() {
+                variables.set_ret(status.unwrap_or(ReturnCode::SUCCESS.into()));
+                return variables.get_ret().convert_err_type();
+            }
+            if variables.has_flag('x') {
+                trace(
+                    &expanded.iter().map(|word| trace_word(word)).join(" "),
+                    variables,
                 );
             }
             if commands.functions.contains_key(&expanded[0]) {
@@ -525,25 +1255,29 @@ pub fn eval(
                         variables.params.push(part.into());
                     }
                 }
+                // A function called from another one is still in a function when it returns.
+                let in_function = global_env.in_function();
                 global_env.set_function(true);
-                for inc in commands
+                // The loops around the call can't be left from inside the function.
+                let loops = mem::take(&mut global_env.loops);
+                let body = commands
                     .functions
-                    .clone()
                     .get(&expanded[0])
                     .unwrap()
                     .contents
-                    .as_ref()
-                    .unwrap()
-                {
-                    let ret = eval(inc.to_owned(), variables, commands, global_env);
-                    variables.set_ret(ret);
+                    .clone()
+                    .unwrap();
+                eval_block(&body, variables, commands, global_env);
+                if global_env.flow == Some(ControlFlow::Return) {
+                    global_env.flow = None;
                 }
+                global_env.loops = loops;
                 // Bring them back.
                 // Remove all local variables.
                 variables
                     .vars
                     .retain(|_, v| v.shell_lvl != ElviGlobal::Local);
-                global_env.set_function(false);
+                global_env.set_function(in_function);
                 variables.new_parameters(&current_params);
                 return variables.get_ret().convert_err_type();
             }
@@ -560,7 +1294,7 @@ pub fn eval(
                             return variables.get_ret().convert_err_type();
                         }
                     };
-                    variables.set_ret(foop.wait().unwrap().code().unwrap().into());
+                    variables.set_ret(foop.wait().unwrap().into());
                 }
                 Err(oops) => {
                     eprintln!("{oops}");
@@ -570,32 +1304,93 @@ pub fn eval(
         }
         Actions::Null => {}
         Actions::IfStatement(if_stmt) => {
-            // Run the condition
-            eval(if_stmt.condition, variables, commands, global_env);
-            // Did we succeed?
-            if variables.get_ret().convert_err_type().get() == ReturnCode::SUCCESS {
-                for act in if_stmt.then_block {
-                    let ret = eval(act, variables, commands, global_env);
-                    variables.set_ret(ret);
+            let Conditional {
+                condition,
+                then_block,
+                elif_block,
+                else_block,
+            } = *if_stmt;
+            let branches = iter::once((condition, then_block)).chain(
+                elif_block
+                    .into_iter()
+                    .flatten()
+                    .map(|elif| (elif.condition, elif.then_block)),
+            );
+            // If no branch runs, the if statement succeeds.
+            let mut ret: ReturnCode = ReturnCode::SUCCESS.into();
+            let mut ran_branch = false;
+            for (condition, block) in branches {
+                // Run the condition
+                let cond_ret = eval_ignoring_errexit(condition, variables, commands, global_env);
+                variables.set_ret(cond_ret);
+                if global_env.flow.is_some() {
+                    return cond_ret;
                 }
-            } else if let Some(components) = if_stmt.else_block {
-                for act in components {
-                    let ret = eval(act, variables, commands, global_env);
-                    variables.set_ret(ret);
+                // Did we succeed?
+                if cond_ret.get() == ReturnCode::SUCCESS {
+                    ret = eval_block(&block, variables, commands, global_env);
+                    ran_branch = true;
+                    break;
                 }
             }
+            if let (false, Some(components)) = (ran_branch, else_block) {
+                ret = eval_block(&components, variables, commands, global_env);
+            }
+            variables.set_ret(ret);
+        }
+        Actions::WhileLoop(loop_things) => {
+            let ret = eval_conditional_loop(&loop_things, false, variables, commands, global_env);
+            variables.set_ret(ret);
+        }
+        Actions::UntilLoop(loop_things) => {
+            let ret = eval_conditional_loop(&loop_things, true, variables, commands, global_env);
+            variables.set_ret(ret);
+        }
+        Actions::CaseStatement(mut case_stmt) => {
+            expand_substitutions(&mut case_stmt.word, variables, commands, global_env);
+            if global_env.flow.is_some() {
+                return variables.get_ret().convert_err_type();
+            }
+            let word = case_stmt.word.to_string();
+            let mut effects = SideEffects::default();
+            let mut substitute = substitute(variables, commands, global_env, &mut effects);
+            // Patterns are only expanded when they're tried, in order, and only the first branch
+            // that matches is run.
+            let matched = case_stmt.items.iter().find(|item| {
+                item.patterns.iter().any(|pattern| {
+                    Pattern::from_parts(pattern, variables, &mut substitute).matches(&word)
+                })
+            });
+            drop(substitute);
+            effects.apply(variables, global_env);
+            if global_env.flow.is_some() {
+                return variables.get_ret().convert_err_type();
+            }
+            let mut ret: ReturnCode = ReturnCode::SUCCESS.into();
+            if let Some(item) = matched {
+                ret = eval_block(&item.body, variables, commands, global_env);
+            }
+            variables.set_ret(ret);
         }
         Actions::ForLoop(loop_things) => {
             let mut new_loop_elements = vec![];
-            for element in &loop_things.elements {
-                for entry in element
-                    .tilde_expansion(variables)
-                    .eval_variables(variables)
-                    .expand_globs()
-                {
-                    new_loop_elements.push(entry);
+            match &loop_things.elements {
+                Some(elements) => {
+                    for element in elements {
+                        let (fields, _) = expand_word(element, variables, commands, global_env);
+                        if global_env.flow.is_some() {
+                            return variables.get_ret().convert_err_type();
+                        }
+                        new_loop_elements.extend(fields.into_iter().map(ElviType::String));
+                    }
+                }
+                None => {
+                    for param in variables.params.iter().skip(1) {
+                        new_loop_elements.push(param.contents.clone());
+                    }
                 }
             }
+            global_env.loops += 1;
             for var in &new_loop_elements {
                 // Ok so now I want to update the variable if it exists before, and if not, create a
                 // new variable.
@@ -606,13 +1401,17 @@ pub fn eval(
                             contents: var.clone(),
                             modification_status: template.modification_status,
                             shell_lvl: template.shell_lvl,
+                            exported: template.exported,
                             line: template.line,
                         },
                     ) {
                         Ok(()) => {}
+                        // Like any other assignment, this leaves the subshell it is in.
                         Err(e) => {
-                            eprintln!("{e}");
-                            std::process::exit(ReturnCode::FAILURE.into());
+                            let mut effects = SideEffects::default();
+                            effects.fail(&e);
+                            effects.apply(variables, global_env);
+                            break;
                         }
                     }
                 } else {
@@ -621,11 +1420,12 @@ pub fn eval(
                         var.clone()
                     ).unwrap() /* I'm reasonably confident that this won't fail */;
                 }
-                for act in &loop_things.do_block {
-                    let ret = eval(act.to_owned(), variables, commands, global_env);
-                    variables.set_ret(ret);
+                eval_block(&loop_things.do_block, variables, commands, global_env);
+                if global_env.end_iteration() {
+                    break;
                 }
             }
+            global_env.loops -= 1;
         }
         Actions::FunctionDeclaration(func) => {
             commands.register_function(func);
@@ -633,39 +1433,221 @@ pub fn eval(
         Actions::Subshell(stmts) => {
             global_env.update_shlvl(1, variables);
             let mut passed_vars = variables.clone();
-            for (idx, act) in stmts.iter().enumerate() {
-                let ret = eval(act.to_owned(), &mut passed_vars, commands, global_env);
-                // If we are at the end of the statement list, we should set the global state
-                // return:
-                // (
-                //   bla # Nothing
-                //   bla # Exit code here
-                // )
-                if idx == stmts.len() {
+            let ret = eval_block(&stmts, &mut passed_vars, commands, global_env);
+            // Whatever left the subshell early doesn't go any further.
+            global_env.flow = None;
+            global_env.update_shlvl(-1, variables);
+            variables.set_ret(ret);
+            variables
+                .vars
+                .retain(|_, v| v.shell_lvl <= ElviGlobal::Normal(global_env.subshells_in))
+        }
+        Actions::And(left, right) => {
+            let ret = eval_ignoring_errexit(*left, variables, commands, global_env);
+            variables.set_ret(ret);
+            if ret.get() == ReturnCode::SUCCESS && global_env.flow.is_none() {
+                let ret = eval(*right, variables, commands, global_env);
+                variables.set_ret(ret);
+            }
+        }
+        Actions::Or(left, right) => {
+            let ret = eval_ignoring_errexit(*left, variables, commands, global_env);
+            variables.set_ret(ret);
+            if ret.get() != ReturnCode::SUCCESS && global_env.flow.is_none() {
+                let ret = eval(*right, variables, commands, global_env);
+                variables.set_ret(ret);
+            }
+        }
+        Actions::Not(action) => {
+            let ret = eval_ignoring_errexit(*action, variables, commands, global_env);
+            variables.set_ret(!ret);
+        }
+        Actions::Redirect(action, mut redirections) => {
+            for redirection in &mut redirections {
+                expand_redirection(redirection, variables, commands, global_env);
+                if global_env.flow.is_some() {
+                    return variables.get_ret().convert_err_type();
+                }
+            }
+            match apply_redirections(&redirections, variables) {
+                Ok(saved) => {
+                    // Simple commands are checked for `-e` by `eval`, once this is all undone.
+                    let ret = eval_action(*action, variables, commands, global_env);
+                    restore_redirections(saved);
                     variables.set_ret(ret);
                 }
+                Err(oops) => {
+                    eprintln!("{oops}");
+                    variables.set_ret(oops.ret());
+                    errexit(oops.ret(), variables, global_env);
+                }
             }
-            global_env.update_shlvl(-1, variables);
-            variables.vars.retain(|_, v| {
-                v.shell_lvl <= ElviGlobal::Normal(global_env.subshells_in)
-                    || v.shell_lvl == ElviGlobal::Global
-            })
         }
-        Actions::CompoundBrackets(stmts) => {
-            for act in &stmts {
-                let ret = eval(act.to_owned(), variables, commands, global_env);
+        Actions::Pipeline(stages) => {
+            let ret = execute_pipeline(stages, variables, commands, global_env);
+            variables.set_ret(ret);
+        }
+        Actions::Assigned(assignments, mut action) => {
+            // The command is expanded before anything is assigned, so `x=1 echo $x` doesn't see it.
+            let status = expand_simple_command(&mut action, variables, commands, global_env);
+            if global_env.flow.is_some() {
+                return variables.get_ret().convert_err_type();
+            }
+            if action.keeps_assignments() {
+                for (name, mut var) in assignments {
+                    change_variable(variables, commands, global_env, &name, &mut var);
+                    if global_env.flow.is_some() {
+                        return variables.get_ret().convert_err_type();
+                    }
+                }
+                if let Some(status) = status {
+                    variables.set_ret(status);
+                }
+                let ret = eval(*action, variables, commands, global_env);
+                variables.set_ret(ret);
+            } else {
+                // Everything else gets them in its environment, and they're put back afterwards,
+                // according to
+                // <https://pubs.opengroup.org/onlinepubs/9699919799/utilities/V3_chap02.html#tag_18_09_01>.
+                let previous: Vec<(String, Option<Variable>)> = assignments
+                    .iter()
+                    .map(|(name, _)| (name.clone(), variables.get_variable(name).cloned()))
+                    .collect();
+                for (name, mut var) in assignments {
+                    var.exported = true;
+                    change_variable(variables, commands, global_env, &name, &mut var);
+                    if global_env.flow.is_some() {
+                        break;
+                    }
+                }
+                let ret = if global_env.flow.is_some() {
+                    variables.get_ret().convert_err_type()
+                } else {
+                    eval(*action, variables, commands, global_env)
+                };
+                for (name, var) in previous {
+                    match var {
+                        Some(var) => variables.vars.insert(name, var),
+                        None => variables.vars.remove(&name),
+                    };
+                }
                 variables.set_ret(ret);
             }
         }
+        Actions::Background(action) => {
+            let ret = execute_background(*action, variables, commands, global_env);
+            variables.set_ret(ret);
+        }
+        Actions::CompoundBrackets(stmts) => {
+            let ret = eval_block(&stmts, variables, commands, global_env);
+            variables.set_ret(ret);
+        }
+        Actions::Statement(line, action) => {
+            variables.set_line(line);
+            let ret = eval(*action, variables, commands, global_env);
+            variables.set_ret(ret);
+        }
     }
     ReturnCode::ret(variables.get_ret().convert_err_type().get())
 }
 
+/// Print a command to standard error after `$PS4`, like `-x` does.
+fn trace(line: &str, variables: &Variables) {
+    let prompt = variables.get_variable("PS4").map_or_else(
+        || "+ ".into(),
+        |ps4| ps4.contents.eval_variables(variables).to_string(),
+    );
+    eprintln!("{prompt}{line}");
+}
+
+/// Quote a word for [`trace`], unless it's plain enough to be read back in as it is.
+fn trace_word(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "%+,-./:=@_".contains(c))
+    {
+        word.to_string()
+    } else {
+        quote(word)
+    }
+}
+
+/// [`trace`] a builtin, whose words have been expanded, apart from those of `test`, which expands
+/// them itself.
+fn trace_builtin(built: &mut Builtins, variables: &Variables) {
+    let mut words = vec![built.name().to_string()];
+    if let Builtins::Test(invert, options) = built {
+        if *invert {
+            words.push("!".into());
+        }
+        let operator = options.operator();
+        let mut operands: Vec<String> = options
+            .words_mut()
+            .into_iter()
+            .map(|word| trace_word(&word.eval_variables(variables).to_string()))
+            .collect();
+        if let Some(operator) = operator {
+            // Unary operators go first, and binary ones go in between.
+            let at = operands.len() - 1;
+            operands.insert(at, operator.to_string());
+        }
+        words.append(&mut operands);
+    } else {
+        words.extend(
+            built
+                .words_mut()
+                .into_iter()
+                .map(|word| trace_word(&word.to_string())),
+        );
+    }
+    trace(&words.join(" "), variables);
+}
+
+/// Expand the words of a simple command into fields ahead of time, leaving anything else alone.
+///
+/// # Returns
+/// The return code of the last command substitution run, if there were any.
+fn expand_simple_command(
+    action: &mut Actions,
+    variables: &mut Variables,
+    commands: &mut Commands,
+    global_env: &mut Env,
+) -> Option<ReturnCode> {
+    let words = match action {
+        Actions::Redirect(inner, _) => {
+            return expand_simple_command(inner, variables, commands, global_env)
+        }
+        Actions::Command(words) => words,
+        Actions::Builtin(built) => match built.arguments_mut() {
+            Some(Some(words)) => words,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let mut fields = vec![];
+    let mut status = None;
+    for word in words.iter() {
+        let (expanded, ret) = expand_word(word, variables, commands, global_env);
+        if global_env.flow.is_some() {
+            break;
+        }
+        fields.extend(expanded.into_iter().map(ElviType::String));
+        status = ret.or(status);
+    }
+    *words = fields;
+    status
+}
+
 #[cfg(test)]
 mod tests {
     use pest::Parser;
 
-    use super::{ElviParser, Rule};
+    use super::{eval_code, ElviParser, Rule};
+    use crate::internal::commands::Commands;
+    use crate::internal::env::{ControlFlow, Env};
+    use crate::internal::status::ReturnCode;
+    use crate::internal::variables::Variables;
 
     #[test]
     fn double_quote_string_is_chill() {
@@ -680,4 +1662,596 @@ mod tests {
         let parse = ElviParser::parse(Rule::singleQuoteString, stringo).unwrap();
         assert_eq!(r#"'foobar'"#, parse.as_str());
     }
+
+    #[test]
+    fn ansi_c_string_keeps_escaped_quotes() {
+        let stringo = r"$'it\'s'";
+        let parse = ElviParser::parse(Rule::ansiCString, stringo).unwrap();
+        assert_eq!(stringo, parse.as_str());
+    }
+
+    #[test]
+    fn pipeline_has_every_stage() {
+        let stringo = "echo foo | tr 'a-z' 'A-Z' |cat";
+        let parse = ElviParser::parse(Rule::pipeline, stringo)
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(stringo, parse.as_str());
+        assert_eq!(3, parse.into_inner().count());
+    }
+
+    #[test]
+    fn redirections_keep_fd_and_target() {
+        for stringo in ["2>&1", ">>log", "< input", "3<>file", ">&-"] {
+            let parse = ElviParser::parse(Rule::redirection, stringo).unwrap();
+            assert_eq!(stringo, parse.as_str());
+        }
+        let parse = ElviParser::parse(Rule::pipelineCommand, "ls 2>/dev/null -l >out")
+            .unwrap()
+            .next()
+            .unwrap();
+        let rules: Vec<Rule> = parse
+            .into_inner()
+            .next()
+            .unwrap()
+            .into_inner()
+            .next()
+            .unwrap()
+            .into_inner()
+            .map(|pair| pair.as_rule())
+            .collect();
+        assert_eq!(
+            vec![
+                Rule::elviWord,
+                Rule::redirection,
+                Rule::elviWord,
+                Rule::redirection
+            ],
+            rules
+        );
+    }
+
+    #[test]
+    fn here_document_body_ends_at_delimiter() {
+        let stringo = "cat <<-'EOF' | wc -l\n\tfoo $bar\n\tEOF\necho done";
+        let parse = ElviParser::parse(Rule::program, stringo)
+            .unwrap()
+            .next()
+            .unwrap();
+        let statements: Vec<&str> = parse
+            .into_inner()
+            .filter(|pair| pair.as_rule() == Rule::statement)
+            .map(|pair| pair.as_str())
+            .collect();
+        assert_eq!(
+            vec!["cat <<-'EOF' | wc -l\n\tfoo $bar\n\tEOF", "echo done"],
+            statements
+        );
+    }
+
+    #[test]
+    fn only_stripped_here_documents_end_at_tabbed_delimiters() {
+        let stringo = "cat <<EOF\n\tEOF\nEOF\necho done";
+        let parse = ElviParser::parse(Rule::program, stringo)
+            .unwrap()
+            .next()
+            .unwrap();
+        let statements: Vec<&str> = parse
+            .into_inner()
+            .filter(|pair| pair.as_rule() == Rule::statement)
+            .map(|pair| pair.as_str())
+            .collect();
+        assert_eq!(vec!["cat <<EOF\n\tEOF\nEOF", "echo done"], statements);
+    }
+
+    #[test]
+    fn quoting_part_of_a_delimiter_quotes_all_of_it() {
+        for stringo in ["cat <<E\"OF\"\n$x\nEOF\n", "cat <<\\EOF\n$x\nEOF\n"] {
+            let parse = ElviParser::parse(Rule::statement, stringo)
+                .unwrap()
+                .next()
+                .unwrap();
+            let delimiter = parse
+                .into_inner()
+                .flatten()
+                .find(|pair| pair.as_rule() == Rule::hereDocumentDelimiter)
+                .unwrap();
+            let pieces: Vec<(Rule, &str)> = delimiter
+                .into_inner()
+                .map(|pair| (pair.as_rule(), pair.as_str()))
+                .collect();
+            assert_eq!(
+                "EOF",
+                pieces.iter().map(|(_, text)| *text).collect::<String>()
+            );
+            assert!(pieces
+                .iter()
+                .any(|(rule, _)| *rule != Rule::unquotedDelimiter));
+        }
+    }
+
+    #[test]
+    fn and_or_list_keeps_operators_in_order() {
+        let stringo = "! mkdir -p d && cd d || exit 1";
+        let parse = ElviParser::parse(Rule::andOrList, stringo)
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(stringo, parse.as_str());
+        let operators: Vec<&str> = parse
+            .into_inner()
+            .filter(|pair| pair.as_rule() == Rule::andOrOperator)
+            .map(|pair| pair.as_str())
+            .collect();
+        assert_eq!(vec!["&&", "||"], operators);
+    }
+
+    #[test]
+    fn keywords_need_a_word_boundary() {
+        for stringo in [
+            "find",
+            "format",
+            "install",
+            "done_file",
+            "do-it",
+            "fi:",
+            "if^x",
+        ] {
+            assert!(ElviParser::parse(Rule::externalCommand, stringo).is_ok());
+        }
+        for stringo in ["done", "do", "until"] {
+            assert!(ElviParser::parse(Rule::externalCommand, stringo).is_err());
+        }
+        // Builtins end the same way.
+        for stringo in ["settings", "set:x", "set^x", "set\"x\""] {
+            assert!(ElviParser::parse(Rule::builtinSet, stringo).is_err());
+        }
+        for stringo in ["set", "set -e", "set>/dev/null", "set;"] {
+            assert!(ElviParser::parse(Rule::builtinSet, stringo).is_ok());
+        }
+    }
+
+    #[test]
+    fn until_loop_splits_condition_and_body() {
+        let stringo = "until test -f foo\ndo touch foo; echo bar\ndone";
+        let parse = ElviParser::parse(Rule::whileLoop, stringo)
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(stringo, parse.as_str());
+        assert_eq!(4, parse.into_inner().count());
+    }
+
+    #[test]
+    fn case_items_keep_every_pattern() {
+        let stringo = "case $1 in\n  (-h|--help) echo help ;;\n  'foo'*) echo foo\nesac";
+        let parse = ElviParser::parse(Rule::caseStatement, stringo)
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(stringo, parse.as_str());
+        let patterns: Vec<&str> = parse
+            .into_inner()
+            .filter(|pair| pair.as_rule() == Rule::caseItem)
+            .flat_map(|pair| pair.into_inner())
+            .filter(|pair| pair.as_rule() == Rule::casePattern)
+            .map(|pair| pair.as_str())
+            .collect();
+        assert_eq!(vec!["-h", "--help", "'foo'*"], patterns);
+    }
+
+    #[test]
+    fn if_statement_has_every_elif() {
+        let stringo = "if a; b; then c\nelif d; then e\nelif f; then g\nelse h\nfi";
+        let parse = ElviParser::parse(Rule::ifStatement, stringo)
+            .unwrap()
+            .next()
+            .unwrap();
+        assert_eq!(stringo, parse.as_str());
+        let elifs = parse
+            .into_inner()
+            .filter(|pair| pair.as_rule() == Rule::elifBlock)
+            .count();
+        assert_eq!(2, elifs);
+    }
+
+    #[test]
+    fn command_substitutions_nest_inside_words() {
+        let parse = ElviParser::parse(Rule::externalCommand, r#"cp a "$(dirname "$f")/b" c"#)
+            .unwrap()
+            .next()
+            .unwrap();
+        let words: Vec<&str> = parse.into_inner().map(|pair| pair.as_str()).collect();
+        assert_eq!(vec!["cp", "a", r#""$(dirname "$f")/b""#, "c"], words);
+        for stringo in [
+            "$(echo $(date))",
+            "pre$(echo ')' \\) \"(\")post",
+            "`echo \\`date\\``.txt",
+        ] {
+            let parse = ElviParser::parse(Rule::elviSingleWord, stringo).unwrap();
+            assert_eq!(stringo, parse.as_str());
+        }
+    }
+
+    #[test]
+    fn parameter_expansions_are_one_word() {
+        let parse = ElviParser::parse(Rule::externalCommand, r#"echo ${x:-"a b"} "${f%"}"}" ${#}"#)
+            .unwrap()
+            .next()
+            .unwrap();
+        let words: Vec<&str> = parse.into_inner().map(|pair| pair.as_str()).collect();
+        assert_eq!(
+            vec!["echo", r#"${x:-"a b"}"#, r#""${f%"}"}""#, "${#}"],
+            words
+        );
+        for stringo in ["${p##*/}.bak", "${x:=${y:-$(echo })}}", "${x:+'}'}"] {
+            let parse = ElviParser::parse(Rule::elviSingleWord, stringo).unwrap();
+            assert_eq!(stringo, parse.as_str());
+        }
+    }
+
+    #[test]
+    fn words_only_stop_at_metacharacters() {
+        let parse = ElviParser::parse(Rule::externalCommand, "curl http://h/#frag a^b café")
+            .unwrap()
+            .next()
+            .unwrap();
+        let words: Vec<&str> = parse.into_inner().map(|pair| pair.as_str()).collect();
+        assert_eq!(vec!["curl", "http://h/#frag", "a^b", "café"], words);
+
+        let (_, variables, _) = run("x=a#b y=#fff # comment\nz=café #comment\n");
+        assert_eq!("a#b", value(&variables, "x"));
+        assert_eq!("#fff", value(&variables, "y"));
+        assert_eq!("café", value(&variables, "z"));
+    }
+
+    #[test]
+    fn background_statements_end_at_ampersand() {
+        let parse = ElviParser::parse(Rule::program, "sleep 1 & a && b &\nwait $!\n")
+            .unwrap()
+            .next()
+            .unwrap();
+        let statements: Vec<(&str, bool)> = parse
+            .into_inner()
+            .filter(|pair| pair.as_rule() == Rule::statement)
+            .map(|pair| {
+                let background = pair
+                    .clone()
+                    .into_inner()
+                    .any(|inner| inner.as_rule() == Rule::background);
+                (pair.as_str().trim(), background)
+            })
+            .collect();
+        assert_eq!(
+            vec![("sleep 1", true), ("a && b", true), ("wait $!", false)],
+            statements
+        );
+    }
+
+    #[test]
+    fn assignments_can_prefix_a_command() {
+        let parse = ElviParser::parse(Rule::assignedCommand, "CC=clang CFLAGS= make -j4")
+            .unwrap()
+            .next()
+            .unwrap();
+        let parts: Vec<(Rule, &str)> = parse
+            .into_inner()
+            .map(|pair| (pair.as_rule(), pair.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (Rule::normalVariable, "CC=clang"),
+                (Rule::normalVariable, "CFLAGS="),
+                (Rule::externalCommand, "make -j4"),
+            ],
+            parts
+        );
+    }
+
+    #[test]
+    fn words_are_made_of_quoted_parts() {
+        let parse = ElviParser::parse(
+            Rule::externalCommand,
+            r#"cc --prefix="$HOME"/opt a'b'"c"$d"#,
+        )
+        .unwrap()
+        .next()
+        .unwrap();
+        let parts: Vec<Vec<Rule>> = parse
+            .into_inner()
+            .map(|word| word.into_inner().map(|part| part.as_rule()).collect())
+            .collect();
+        assert_eq!(
+            vec![
+                vec![Rule::elviSingleWord],
+                vec![
+                    Rule::elviSingleWord,
+                    Rule::doubleQuoteString,
+                    Rule::elviSingleWord
+                ],
+                vec![
+                    Rule::elviSingleWord,
+                    Rule::singleQuoteString,
+                    Rule::doubleQuoteString,
+                    Rule::elviSingleWord
+                ],
+            ],
+            parts
+        );
+    }
+
+    #[test]
+    fn backslash_newline_continues_the_line() {
+        let parse = ElviParser::parse(
+            Rule::program,
+            "echo one \\\n  two\\\nthree\ntrue \\\n&& false\n",
+        )
+        .unwrap()
+        .next()
+        .unwrap();
+        let statements: Vec<&str> = parse
+            .into_inner()
+            .filter(|pair| pair.as_rule() == Rule::statement)
+            .map(|pair| pair.as_str().trim())
+            .collect();
+        assert_eq!(
+            vec!["echo one \\\n  two\\\nthree", "true \\\n&& false"],
+            statements
+        );
+    }
+
+    #[test]
+    fn loops_can_be_left_early() {
+        let parse = ElviParser::parse(
+            Rule::forLoop,
+            "for i in 1 2; do [ $i = 2 ] && continue; break 2; done",
+        )
+        .unwrap()
+        .next()
+        .unwrap();
+        let builtins: Vec<Rule> = parse
+            .into_inner()
+            .flatten()
+            .map(|pair| pair.as_rule())
+            .filter(|rule| matches!(rule, Rule::builtinBreak | Rule::builtinContinue))
+            .collect();
+        assert_eq!(vec![Rule::builtinContinue, Rule::builtinBreak], builtins);
+        assert!(ElviParser::parse(Rule::builtinReturn, "returned").is_err());
+    }
+
+    #[test]
+    fn export_keeps_assignments_whole() {
+        let parse = ElviParser::parse(Rule::builtinExport, "export -p PATH=~/bin:\"$PATH\" EDITOR")
+            .unwrap()
+            .next()
+            .unwrap();
+        let parts: Vec<(Rule, &str)> = parse
+            .into_inner()
+            .map(|pair| (pair.as_rule(), pair.as_str()))
+            .collect();
+        assert_eq!(
+            vec![
+                (Rule::elviWord, "-p"),
+                (Rule::normalVariable, "PATH=~/bin:\"$PATH\""),
+                (Rule::elviWord, "EDITOR"),
+            ],
+            parts
+        );
+    }
+
+    /// Run `code` the way a script is run, returning its return code, along with the variables
+    /// and environment it left behind.
+    fn run(code: &str) -> (u16, Variables, Env) {
+        let mut variables = Variables::default();
+        variables.fill_defaults();
+        // Calling a function needs `$0`, which the shell always has.
+        variables.new_parameters(&["elvi".to_string().into()]);
+        let mut commands = Commands::generate(&variables);
+        let mut env = Env::default();
+        let ret = eval_code(code, &mut variables, &mut commands, &mut env);
+        (ret.get(), variables, env)
+    }
+
+    /// The value of a variable after [`run`], which has to be set.
+    fn value(variables: &Variables, name: &str) -> String {
+        variables.get_variable(name).unwrap().contents.to_string()
+    }
+
+    #[test]
+    fn errexit_ignores_conditions_and_lists() {
+        let (ret, variables, env) = run("set -e
+if [ a = b ]; then reached=0; fi
+while [ a = b ]; do reached=0; done
+[ a = b ] && reached=0
+! [ a = a ]
+reached=1
+[ a = b ]
+reached=2
+");
+        assert_eq!(ReturnCode::FAILURE, ret);
+        assert_eq!(Some(ControlFlow::Exit), env.flow);
+        assert_eq!("1", value(&variables, "reached"));
+    }
+
+    #[test]
+    fn command_substitutions_can_call_functions() {
+        let (ret, variables, _) = run("greet() { echo \"hello $1\"; }
+x=$(greet world)
+y=`greet there`
+");
+        assert_eq!(ReturnCode::SUCCESS, ret);
+        assert_eq!("hello world", value(&variables, "x"));
+        assert_eq!("hello there", value(&variables, "y"));
+    }
+
+    #[test]
+    fn break_and_continue_leave_enclosing_loops() {
+        let (ret, variables, env) = run("seen=
+for i in 1 2 3
+do
+    for j in a b
+    do
+        [ $i = 2 ] && break 2
+        seen=$seen$i$j
+    done
+done
+skipped=
+for i in 1 2
+do
+    for j in a b
+    do
+        [ $j = b ] && continue 2
+        skipped=$skipped$i$j
+    done
+    skipped=${skipped}never
+done
+");
+        assert_eq!(ReturnCode::SUCCESS, ret);
+        assert_eq!(None, env.flow);
+        assert_eq!(0, env.loops);
+        assert_eq!("1a1b", value(&variables, "seen"));
+        assert_eq!("1a2a", value(&variables, "skipped"));
+    }
+
+    #[test]
+    fn return_only_leaves_the_function() {
+        let (ret, variables, env) = run("f() {
+    for i in 1 2 3
+    do
+        [ $i = 2 ] && return 7
+        seen=$seen$i
+    done
+    seen=never
+}
+calls=
+for k in 1 2
+do
+    f
+    status=$?
+    calls=$calls$k
+done
+");
+        assert_eq!(ReturnCode::SUCCESS, ret);
+        assert_eq!(None, env.flow);
+        assert_eq!("11", value(&variables, "seen"));
+        assert_eq!("7", value(&variables, "status"));
+        assert_eq!("12", value(&variables, "calls"));
+
+        let (ret, variables, env) = run("return 3\nstatus=$?\n");
+        assert_eq!(ReturnCode::SUCCESS, ret);
+        assert_eq!(None, env.flow);
+        assert_eq!("2", value(&variables, "status"));
+    }
+
+    #[test]
+    fn functions_called_from_functions_keep_locals_working() {
+        let (ret, variables, env) = run("inner() { y=1; }
+outer() {
+    inner
+    local x=1
+    reached=$x
+}
+outer
+after=yes
+");
+        assert_eq!(ReturnCode::SUCCESS, ret);
+        assert_eq!(None, env.flow);
+        assert_eq!("1", value(&variables, "reached"));
+        assert_eq!("yes", value(&variables, "after"));
+        assert!(!env.in_function());
+    }
+
+    #[test]
+    fn exit_only_leaves_the_subshell() {
+        let (ret, variables, env) = run("(
+    for i in 1 2
+    do
+        if [ $i = 2 ]; then exit 3; fi
+        inside=$i
+    done
+    inside=never
+)
+status=$?
+after=yes
+");
+        assert_eq!(ReturnCode::SUCCESS, ret);
+        assert_eq!(None, env.flow);
+        assert_eq!("3", value(&variables, "status"));
+        assert_eq!("yes", value(&variables, "after"));
+        assert!(variables.get_variable("inside").is_none());
+    }
+
+    #[test]
+    fn failures_only_leave_the_subshell() {
+        for failing in [
+            "echo $((1 / 0))",
+            "x=$((1 / 0))",
+            "set -u\n[ -n \"$nope\" ]",
+            "case a in ${nope?}) ;; esac",
+            "readonly x=1\nx=2",
+            "readonly y=1\nfor y in 2 3\ndo\n    inside=never\ndone",
+            "local z=1",
+        ] {
+            let (ret, variables, env) = run(&format!(
+                "(\n{failing}\ninside=never\n)\nstatus=$?\nafter=yes\n"
+            ));
+            assert_eq!(ReturnCode::SUCCESS, ret, "{failing}");
+            assert_eq!(None, env.flow, "{failing}");
+            assert_eq!("2", value(&variables, "status"), "{failing}");
+            assert_eq!("yes", value(&variables, "after"), "{failing}");
+            assert!(variables.get_variable("inside").is_none(), "{failing}");
+
+            let (ret, variables, env) = run(&format!("{failing}\nafter=yes\n"));
+            assert_eq!(ReturnCode::MISUSE, ret, "{failing}");
+            assert_eq!(Some(ControlFlow::Exit), env.flow, "{failing}");
+            assert!(variables.get_variable("after").is_none(), "{failing}");
+        }
+    }
+
+    #[test]
+    fn test_can_be_inverted_in_both_spellings() {
+        let (_, variables, _) = run("test ! a = b\nword=$?\n[ ! -z x ]\nbrackets=$?\n");
+        assert_eq!("0", value(&variables, "word"));
+        assert_eq!("0", value(&variables, "brackets"));
+    }
+
+    #[test]
+    fn case_patterns_are_expanded_when_tried() {
+        let (_, variables, _) = run("case foo in
+    b$(echo a)r) first=yes ;;
+    f$(echo '?')*) second=yes ;;
+    ${late:=ran}) third=yes ;;
+esac
+case 'a*' in
+    a\\*) escaped=yes ;;
+esac
+");
+        assert!(variables.get_variable("first").is_none());
+        assert_eq!("yes", value(&variables, "second"));
+        assert!(variables.get_variable("third").is_none());
+        assert!(variables.get_variable("late").is_none());
+        assert_eq!("yes", value(&variables, "escaped"));
+    }
+
+    #[test]
+    fn errexit_checks_assignments_on_their_own() {
+        let (ret, variables, env) = run("set -e\na=1 b=$(false)\nafter=yes\n");
+        assert_eq!(ReturnCode::FAILURE, ret);
+        assert_eq!(Some(ControlFlow::Exit), env.flow);
+        assert_eq!("1", value(&variables, "a"));
+        assert!(variables.get_variable("after").is_none());
+    }
+
+    #[test]
+    fn here_document_substitutions_can_span_lines() {
+        let out = std::env::temp_dir().join(format!("elvi-here-{}", std::process::id()));
+        run(&format!(
+            "cat >'{}' <<EOF\n$(echo a\necho b) $((1 +\n2))\nEOF\n",
+            out.display()
+        ));
+        let written = std::fs::read_to_string(&out).unwrap();
+        let _ = std::fs::remove_file(&out);
+        assert_eq!("a\nb 3\n", written);
+    }
 }
@@ -10,22 +10,44 @@ pub struct Args {
     #[clap(flatten)]
     pub group: Group,
 
+    /// Turn on a non-POSIX extension, can be given more than once.
+    #[clap(long = "extension", value_enum)]
+    pub extensions: Vec<Extension>,
+
+    /// Start without importing any variables from the environment.
+    #[clap(long = "clean-env")]
+    pub clean_env: bool,
+
+    /// Only import this variable from the environment, can be given more than once.
+    #[clap(long = "keep-env", value_name = "NAME")]
+    pub keep_env: Vec<String>,
+
     /// Positional variables
     pub positionals: Option<Vec<String>>,
 }
 
+/// Non-POSIX extensions to the language, which are all off by default.
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Extension {
+    /// Allow `elsie` in place of `else`.
+    Elsie,
+    /// Replace backslash escapes in `$'...'` strings, like `$'a\tb'`.
+    AnsiCStrings,
+    /// Say which line failed when `set -e` exits the shell.
+    VerboseErrors,
+}
+
 #[derive(Debug, clap::Args)]
-#[group(required = true, multiple = false)]
+#[group(required = true, multiple = true)]
 pub struct Group {
     /// Read commands from the `command_string` operand instead of from the standard input.
     ///
     /// Special parameter 0 will be set from the `command_name` operand
     /// and the positional parameters ($1, $2, etc.)  set
     /// from the remaining argument operands.
-    #[clap(short = 'c', long = None, conflicts_with = "file")]
+    #[clap(short = 'c', long = None)]
     pub read_from_input: Option<String>,
 
-    /// Read from file
-    #[clap(conflicts_with = "read_from_input")]
+    /// Read from file, or with `-c`, the `command_name` operand.
     pub file: Option<PathBuf>,
 }